@@ -0,0 +1,186 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::vec::Vec;
+use std::slice::Items;
+
+use interpolation::{TextPart, Literal};
+
+// the attributes of a tag in the order of their first appearance in the source,
+// each value keeps its compiled interpolations
+#[deriving(Clone, Show, Eq)]
+pub struct Attributes {
+    entries: Vec<(~str, Vec<Vec<TextPart>>)>
+}
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Attributes { entries: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+
+    fn position(&self, name: &str) -> Option<uint> {
+        self.entries.iter().position(|&(ref n, _)| n.as_slice() == name)
+    }
+
+    pub fn find<'a>(&'a self, name: &str) -> Option<&'a Vec<Vec<TextPart>>> {
+        match self.position(name) {
+            Some(i) => { let &(_, ref values) = self.entries.get(i); Some(values) },
+            None    => None
+        }
+    }
+
+    // replace the values of an existing attribute, it keeps its position
+    pub fn insert(&mut self, name: ~str, values: Vec<~str>) {
+        self.insert_parts(name, values.move_iter().map(|v| vec!(Literal(v))).collect())
+    }
+
+    fn insert_parts(&mut self, name: ~str, values: Vec<Vec<TextPart>>) {
+        match self.position(name.as_slice()) {
+            Some(i) => *self.entries.get_mut(i) = (name, values),
+            None    => self.entries.push((name, values))
+        }
+    }
+
+    pub fn push_value(&mut self, name: ~str, value: ~str) {
+        self.push_parts(name, vec!(Literal(value)))
+    }
+
+    fn push_parts(&mut self, name: ~str, parts: Vec<TextPart>) {
+        match self.position(name.as_slice()) {
+            Some(i) => { let &(_, ref mut values) = self.entries.get_mut(i); values.push(parts) },
+            None    => self.entries.push((name, vec!(parts)))
+        }
+    }
+
+    pub fn merge(&mut self, name: ~str, value: ~str) {
+        self.merge_parts(name, vec!(Literal(value)))
+    }
+
+    // class values are accumulated, id values are joined with an underscore
+    // and any other attribute is overridden by the last value.
+    pub fn merge_parts(&mut self, name: ~str, parts: Vec<TextPart>) {
+        match name.as_slice() {
+            "class" => self.push_parts(name.clone(), parts),
+            "id"    => {
+                let mut joined = Vec::new();
+                match self.find("id") {
+                    Some(values) => {
+                        for v in values.iter() {
+                            for part in v.iter() { push_part(&mut joined, part.clone()) }
+                            push_part(&mut joined, Literal("_".to_owned()));
+                        }
+                    },
+                    None         => {}
+                }
+                for part in parts.move_iter() { push_part(&mut joined, part) }
+                self.insert_parts(name.clone(), vec!(joined))
+            },
+            _       => self.insert_parts(name.clone(), vec!(parts))
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> Items<'a, (~str, Vec<Vec<TextPart>>)> {
+        self.entries.iter()
+    }
+
+    // the attributes ordered by name
+    pub fn sorted(&self) -> Attributes {
+        let mut entries = self.entries.clone();
+        entries.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+        Attributes { entries: entries }
+    }
+}
+
+// consecutive literals are kept as a single one
+fn push_part(parts: &mut Vec<TextPart>, part: TextPart) {
+    let text = match part {
+        Literal(text) => text,
+        part          => { parts.push(part); return }
+    };
+    match parts.mut_last() {
+        Some(&Literal(ref mut last)) => { *last = format!("{}{}", *last, text); return },
+        _                            => {}
+    }
+    parts.push(Literal(text))
+}
+
+#[cfg(test)]
+mod test {
+    use attributes::Attributes;
+    use interpolation::{TextPart, Literal, Interpolation};
+
+    fn literals(values: &[&str]) -> Vec<Vec<TextPart>> {
+        values.iter().map(|v| vec!(Literal(v.to_owned()))).collect()
+    }
+
+    #[test]
+    fn keep_insertion_order() {
+        let mut attributes = Attributes::new();
+        attributes.insert("id".to_owned(), vec!("a".to_owned()));
+        attributes.push_value("class".to_owned(), "b".to_owned());
+        attributes.insert("href".to_owned(), vec!("/".to_owned()));
+        attributes.insert("id".to_owned(), vec!("c".to_owned()));
+        let names: Vec<~str> = attributes.iter().map(|&(ref n, _)| n.clone()).collect();
+        assert_eq!(vec!("id".to_owned(), "class".to_owned(), "href".to_owned()), names)
+        assert_eq!(Some(&literals(["c"])), attributes.find("id"))
+    }
+
+    #[test]
+    fn sort_by_name() {
+        let mut attributes = Attributes::new();
+        attributes.insert("id".to_owned(), vec!("a".to_owned()));
+        attributes.insert("class".to_owned(), vec!("b".to_owned()));
+        let names: Vec<~str> = attributes.sorted().iter().map(|&(ref n, _)| n.clone()).collect();
+        assert_eq!(vec!("class".to_owned(), "id".to_owned()), names)
+    }
+
+    #[test]
+    fn merge_class_and_id() {
+        let mut attributes = Attributes::new();
+        attributes.merge("class".to_owned(), "a".to_owned());
+        attributes.merge("class".to_owned(), "b".to_owned());
+        attributes.merge("id".to_owned(), "a".to_owned());
+        attributes.merge("id".to_owned(), "b".to_owned());
+        attributes.merge("href".to_owned(), "a".to_owned());
+        attributes.merge("href".to_owned(), "b".to_owned());
+        assert_eq!(Some(&literals(["a", "b"])), attributes.find("class"))
+        assert_eq!(Some(&literals(["a_b"])), attributes.find("id"))
+        assert_eq!(Some(&literals(["b"])), attributes.find("href"))
+    }
+
+    #[test]
+    fn merge_interpolated_id() {
+        let mut attributes = Attributes::new();
+        attributes.merge("id".to_owned(), "a".to_owned());
+        attributes.merge_parts("id".to_owned(), vec!(Interpolation("b".to_owned())));
+        assert_eq!(Some(&vec!(vec!(Literal("a_".to_owned()), Interpolation("b".to_owned())))),
+                   attributes.find("id"))
+    }
+}
@@ -34,50 +34,145 @@ extern crate haml;
 extern crate colorize;
 
 use std::io;
-use std::io::File;
+use std::io::{File, MemReader};
 use std::path::Path;
 use std::os;
+use std::str;
 
 use colorize::*;
 
-fn get_reader() -> Result<~Reader, ~str> {
+// colours are only used when the diagnostics are written to a terminal
+struct Style {
+    colored: bool
+}
+
+impl Style {
+    fn new() -> Style {
+        Style { colored: io::stdio::stderr_raw().isatty() }
+    }
+
+    fn error(&self, s: &str) -> ~str { if self.colored { s.b_red() } else { s.to_owned() } }
+    fn help(&self, s: &str) -> ~str { if self.colored { s.b_cyan() } else { s.to_owned() } }
+    fn usage(&self, s: &str) -> ~str { if self.colored { s.b_yellow() } else { s.to_owned() } }
+    fn gutter(&self, s: &str) -> ~str { if self.colored { s.b_blue() } else { s.to_owned() } }
+}
+
+// the input name and its reader
+fn get_reader(style: &Style) -> Result<(~str, ~Reader), ~str> {
     let a = os::args();
     if a.len() == 1 {
-        Ok(~io::stdin() as ~Reader)
+        Ok(("<stdin>".to_owned(), ~io::stdin() as ~Reader))
     } else if a.len() == 2 {
         match File::open(&Path::new(a[1].clone())) {
-            Ok(f)   => Ok(~f as ~Reader),
-            Err(_)  => Err(format!("{} {}, no such file or directory.", "error:".b_red(), a[1]))
+            Ok(f)   => Ok((a[1].clone(), ~f as ~Reader)),
+            Err(_)  => Err(format!("{} {}, no such file or directory.", style.error("error:"),
+                                   a[1]))
         }
     } else {
-        Err(format!("{} invalid arguments number: expected 1 but found {}.", "error:".b_red(),
+        Err(format!("{} invalid arguments number: expected 1 but found {}.", style.error("error:"),
                     a.len()))
     }
 }
 
-fn print_usage() {
-    println!("{} ./haml [optional: filepath]", "usage:".b_yellow())
+// the messages go to stderr so they never end up in the generated html
+fn print_err(s: &str) {
+    let _ = io::stderr().write_line(s);
+}
+
+// columns taken on a terminal, wide chars (e.g. CJK) take two
+fn display_width(s: &str) -> uint {
+    s.chars().map(|c| c.width(false).unwrap_or(0)).fold(0, |a, b| a + b)
+}
+
+fn print_usage(style: &Style) {
+    print_err(format!("{} ./haml [optional: filepath]", style.usage("usage:")))
+}
+
+// error: message
+//  --> file:line:col
+//   |
+// 3 |   %p.
+//   |     ^
+//   = help: advice
+fn print_diagnostic(style: &Style, name: &str, source: &str, e: &haml::HamlError) {
+    let line_nb = e.span.line.to_str();
+    let pad = " ".repeat(line_nb.len());
+    print_err(format!("{} {}", style.error("error:"), e.description()));
+    print_err(format!("{}{} {}:{}:{}", pad, style.gutter("-->"), name, e.span.line,
+                      e.span.col));
+    match source.lines().nth(e.span.line as uint - 1) {
+        Some(line) => {
+            let start = match line.char_indices().nth(e.span.col as uint - 1) {
+                Some((i, _)) => i,
+                None         => line.len()
+            };
+            // the marker keeps the tabs of the line and the width of its chars to stay aligned
+            let mut before = StrBuf::new();
+            for c in line.slice_to(start).chars() {
+                if c == '\t' { before.push_char('\t') }
+                else { before.push_str(" ".repeat(c.width(false).unwrap_or(0))) }
+            }
+            let end = if start + e.span.len > line.len() { line.len() }
+                      else { start + e.span.len };
+            let width = display_width(line.slice(start, end));
+            let width = if width > 0 { width } else { 1 };
+            print_err(format!("{} {}", pad, style.gutter("|")));
+            print_err(format!("{} {} {}", line_nb, style.gutter("|"), line));
+            print_err(format!("{} {} {}{}", pad, style.gutter("|"), before,
+                              style.error("^".repeat(width).as_slice())));
+        },
+        None       => {}
+    }
+    match e.help() {
+        Some(help) => {
+            print_err(format!("{} {} {} {}", pad, style.gutter("="), style.help("help:"), help))
+        },
+        None       => {}
+    }
+    print_err("");
 }
 
 fn main() {
-    match get_reader() {
-        Ok(reader)   => {
+    let style = Style::new();
+    match get_reader(&style) {
+        Ok((name, mut reader)) => {
+            // keep the source to show the lines in error
+            let bytes = match reader.read_to_end() {
+                Ok(bytes) => bytes,
+                Err(e)    => {
+                    print_err(format!("{} {}: {}", style.error("error:"), name, e));
+                    return
+                }
+            };
+            let source = str::from_utf8_lossy(bytes.as_slice()).into_owned();
             // parse haml
-            let mut haml_engine = haml::Engine::new(reader, haml::Html5);
-            match haml_engine.execute() {
-                Ok(_)  => { /* nothing to do */ }
-                Err(e) => { println!("{}{}", "syntax error: ".b_red(), e); return }
+            let mut haml_engine = haml::Engine::new(~MemReader::new(bytes) as ~Reader,
+                                                    haml::Html5);
+            // the partials are searched next to the template
+            let root = if name.as_slice() == "<stdin>" { Path::new(".") }
+                       else { Path::new(name.clone()).dir_path() };
+            haml_engine.set_loader(~haml::FileSystemLoader::new(root) as ~haml::TemplateLoader);
+            let errors = haml_engine.execute_recovering();
+            for e in errors.iter() {
+                print_diagnostic(&style, name.as_slice(), source.as_slice(), e);
             }
-            // generate and write html.
-            let mut writer = io::stdout();
-            match haml_engine.generate(&mut writer as &mut Writer) {
+            if errors.len() != 0 { return }
+            // render and write html.
+            let html = match haml_engine.render(&haml::Context::new()) {
+                Ok(html) => html,
+                Err(e)   => {
+                    print_diagnostic(&style, name.as_slice(), source.as_slice(), &e);
+                    return
+                }
+            };
+            match io::stdout().write_str(html) {
                 Ok(()) => { /* nothing to do */ },
                 Err(e) => fail!("error on writing output: {}", e)
             }
         }
         Err(s)  => {
-            println!("{}", s);
-            print_usage();
+            print_err(s);
+            print_usage(&style);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,133 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use collections::HashMap;
+
+use value::{Value, ToValue, Str, Safe};
+use expr::Lookup;
+
+// Implemented by the values used in an object reference `%div[object, :prefix]`.
+pub trait HamlObjectRef {
+    // the type name used to generate the class and the id, e.g. "user"
+    fn object_ref_type(&self) -> ~str;
+
+    // the key used to generate the id, None for an object which has no key yet
+    fn object_ref_key(&self) -> Option<~str>;
+}
+
+pub struct Context {
+    values: HashMap<~str, Value>,
+    objects: HashMap<~str, ~HamlObjectRef>
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            values: HashMap::new(),
+            objects: HashMap::new()
+        }
+    }
+
+    pub fn set<T: ToValue>(&mut self, name: &str, value: T) {
+        self.values.insert(name.to_owned(), value.to_value());
+    }
+
+    pub fn set_value(&mut self, name: &str, value: ~str) {
+        self.values.insert(name.to_owned(), Str(value));
+    }
+
+    // values which are already html and are never escaped
+    pub fn set_safe_value(&mut self, name: &str, value: ~str) {
+        self.values.insert(name.to_owned(), Safe(value));
+    }
+
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a Value> {
+        self.values.find(&name.to_owned())
+    }
+
+    // find a value from a path like "user.name" or "users.0"
+    pub fn lookup(&self, path: &str) -> Option<Value> {
+        let mut keys = path.split('.').map(|k| k.trim());
+        let mut value = match keys.next() {
+            Some(name) => match self.get(name) {
+                Some(v) => v.clone(),
+                None    => return None
+            },
+            None       => return None
+        };
+        for key in keys {
+            value = match value.get(key) {
+                Some(v) => v,
+                None    => return None
+            };
+        }
+        Some(value)
+    }
+
+    pub fn set_object_ref(&mut self, name: &str, object: ~HamlObjectRef) {
+        self.objects.insert(name.to_owned(), object);
+    }
+
+    pub fn get_object_ref<'a>(&'a self, name: &str) -> Option<&'a ~HamlObjectRef> {
+        self.objects.find(&name.to_owned())
+    }
+}
+
+impl Lookup for Context {
+    fn lookup_var(&self, name: &str) -> Option<Value> {
+        self.get(name).map(|v| v.clone())
+    }
+}
+
+// generate the class and the id of an object reference:
+// class="prefix_type" and id="prefix_type_key" (or "prefix_new_type" without key)
+pub fn object_ref_attributes(object: &HamlObjectRef, prefix: Option<~str>) -> (~str, ~str) {
+    let ref_type = object.object_ref_type();
+    let (class, id) = match object.object_ref_key() {
+        Some(key) => (ref_type.clone(), format!("{}_{}", ref_type, key)),
+        None      => (ref_type.clone(), format!("new_{}", ref_type))
+    };
+    match prefix {
+        Some(p) => (format!("{}_{}", p, class), format!("{}_{}", p, id)),
+        None    => (class, id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use collections::TreeMap;
+    use context::Context;
+    use value::{Str, Int, Map};
+
+    #[test]
+    fn lookup_nested_values() {
+        let mut ctx = Context::new();
+        let mut user = TreeMap::new();
+        user.insert("name".to_owned(), Str("John".to_owned()));
+        ctx.set("user", Map(user));
+        ctx.set("numbers", vec!(1i, 2i));
+        assert_eq!(Some(Str("John".to_owned())), ctx.lookup("user.name"))
+        assert_eq!(Some(Int(2)), ctx.lookup("numbers.1"))
+        assert_eq!(None, ctx.lookup("user.email"))
+        assert_eq!(None, ctx.lookup("unknown"))
+    }
+}
@@ -23,9 +23,19 @@
 extern crate collections;
 
 use std::vec::Vec;
-use collections::HashMap;
 use std::fmt;
 
+use attributes::Attributes;
+use format::Html5;
+use options::Options;
+use context::Context;
+use interpolation::{TextPart, Literal};
+use expr::Expr;
+use render::Renderer;
+use loader::TemplateLoader;
+use span::Span;
+use error::HamlError;
+
 #[deriving(Clone, Show, Eq, Ord)]
 pub struct ItemId(Vec<i32>);
 
@@ -33,13 +43,34 @@ pub struct ItemId(Vec<i32>);
 pub enum TagType {
     Block,
     Inline,
+    SelfClosing,
     PlainText,
     Header,
     HamlComment,
     HtmlComment,
+    ConditionalComment,
+    RevealedComment,
+    Filter,
+    // `- if`, `- unless`, `- elsif` and `- else`, one after the other at the same level
+    If,
+    Unless,
+    Elsif,
+    Else,
+    // `- for item in items`, the nested items are rendered for each element
+    For,
+    // `= render "name", key: value`, the tag is the name of the partial
+    Partial,
     Root
 }
 
+// `&` and `&=` escape the content, `!` and `!=` never escape it
+#[deriving(Clone, Show, Eq)]
+pub enum EscapeMode {
+    DefaultEscaping,
+    Escaped,
+    Unescaped
+}
+
 #[deriving(Clone, Eq)]
 pub struct DomTree {
     root: Item,
@@ -51,10 +82,23 @@ pub struct DomTree {
 pub struct Item {
     parent: ItemId,
     childs: Vec<Item>,
-    attributes: HashMap<~str, Vec<~str>>,
+    attributes: Attributes,
     tag: ~str,
-    content: ~str,
-    tag_type: TagType
+    content: Vec<TextPart>,
+    tag_type: TagType,
+    remove_outer_ws: bool,
+    remove_inner_ws: bool,
+    escape: EscapeMode,
+    preserve: bool,
+    // `[object, :prefix]`, resolved with the context when rendering
+    object_ref: Option<(~str, Option<~str>)>,
+    // the condition of a branch or the collection of a loop
+    expr: Option<Expr>,
+    // the variables of a loop
+    vars: Vec<~str>,
+    // the locals given to a partial
+    locals: Vec<(~str, Expr)>,
+    span: Span
 }
 
 impl DomTree {
@@ -66,6 +110,10 @@ impl DomTree {
         }
     }
 
+    pub fn get_root<'a>(&'a self) -> &'a Item {
+        &self.root
+    }
+
     pub fn get_elt<'a>(&'a self, elt_id: ItemId) -> Option<&'a Item> {
         let ItemId(mut tree_path) = elt_id.clone();
         match tree_path.shift() {
@@ -113,6 +161,14 @@ impl DomTree {
         let ItemId(tree_path) = self.cur_elt_id.clone();
         (tree_path.len() - 1) as u32
     }
+
+    // the last item inserted in the current element, haml comments are skipped
+    pub fn get_last_child<'a>(&'a self) -> Option<&'a Item> {
+        match self.get_elt(self.cur_elt_id.clone()) {
+            Some(elt) => elt.get_childs().iter().rev().find(|c| c.tag_type != HamlComment),
+            None      => None
+        }
+    }
 }
 
 fn rec_get_elt<'a>(elt: &'a Item,
@@ -135,86 +191,202 @@ fn rec_insert_elt(elt: &mut Item,
 }
 
 impl Item {
-    pub fn root() -> Item {
+    fn new(tag_type: TagType) -> Item {
         Item {
             parent: ItemId(vec!(0)),
             childs: Vec::new(),
-            attributes: HashMap::new(),
+            attributes: Attributes::new(),
             tag: "".to_owned(),
-            content: "".to_owned(),
-            tag_type: Root
+            content: Vec::new(),
+            tag_type: tag_type,
+            remove_outer_ws: false,
+            remove_inner_ws: false,
+            escape: DefaultEscaping,
+            preserve: false,
+            object_ref: None,
+            expr: None,
+            vars: Vec::new(),
+            locals: Vec::new(),
+            span: Span::line(1)
         }
     }
 
+    pub fn root() -> Item {
+        Item::new(Root)
+    }
+
     pub fn haml_comment() -> Item {
+        Item::new(HamlComment)
+    }
+
+    pub fn html_comment(content: Vec<TextPart>) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
-            attributes: HashMap::new(),
-            tag: "".to_owned(),
-            content: "".to_owned(),
-            tag_type: HamlComment
+            content: content,
+            ..Item::new(HtmlComment)
         }
     }
 
-    pub fn html_comment(content: ~str) -> Item {
+    // `/[if IE]`, the condition is stored as the tag
+    pub fn conditional_comment(condition: ~str, content: Vec<TextPart>, revealed: bool) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
-            attributes: HashMap::new(),
-            tag: "".to_owned(),
+            tag: condition,
             content: content,
-            tag_type: HtmlComment
+            ..Item::new(if revealed { RevealedComment } else { ConditionalComment })
         }
     }
 
     pub fn block(tag: ~str,
-                 attributes: HashMap<~str, Vec<~str>>) -> Item {
+                 attributes: Attributes) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
             attributes: attributes,
             tag: if tag == "".to_owned() { "div".to_owned() } else { tag },
-            content: "".to_owned(),
-            tag_type: Block
+            ..Item::new(Block)
+        }
+    }
+
+    pub fn self_closing(tag: ~str, attributes: Attributes) -> Item {
+        Item {
+            attributes: attributes,
+            tag: tag,
+            ..Item::new(SelfClosing)
         }
     }
 
     pub fn header(header: ~str) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
-            attributes: HashMap::new(),
-            tag: "".to_owned(),
-            content: header,
-            tag_type: Header
+            content: vec!(Literal(header)),
+            ..Item::new(Header)
         }
     }
 
-    pub fn plain_text(text: ~str) -> Item {
+    pub fn plain_text(text: Vec<TextPart>) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
-            attributes: HashMap::new(),
-            tag: "".to_owned(),
             content: text,
-            tag_type: PlainText
+            ..Item::new(PlainText)
         }
     }
 
     pub fn inline(tag: ~str,
-                  attributes: HashMap<~str, Vec<~str>>,
-                  content: ~str) -> Item {
+                  attributes: Attributes,
+                  content: Vec<TextPart>) -> Item {
         Item {
-            parent: ItemId(vec!(0)),
-            childs: Vec::new(),
             attributes: attributes,
             tag: if tag == "".to_owned() { "div".to_owned() } else { tag },
             content: content,
-            tag_type: Inline
+            ..Item::new(Inline)
         }
     }
 
+    pub fn filter(name: ~str, content: Vec<TextPart>) -> Item {
+        Item {
+            tag: name,
+            content: content,
+            ..Item::new(Filter)
+        }
+    }
+
+    // the nested items are rendered if the condition is true, an `else` has no condition
+    pub fn branch(tag_type: TagType, expr: Option<Expr>) -> Item {
+        Item {
+            expr: expr,
+            ..Item::new(tag_type)
+        }
+    }
+
+    pub fn for_loop(vars: Vec<~str>, collection: Expr) -> Item {
+        Item {
+            expr: Some(collection),
+            vars: vars,
+            ..Item::new(For)
+        }
+    }
+
+    pub fn partial(name: ~str, locals: Vec<(~str, Expr)>) -> Item {
+        Item {
+            tag: name,
+            locals: locals,
+            ..Item::new(Partial)
+        }
+    }
+
+    // `>` remove the whitespace around the tag, `<` the whitespace inside it
+    pub fn with_whitespace_removal(mut self, outer: bool, inner: bool) -> Item {
+        self.remove_outer_ws = outer;
+        self.remove_inner_ws = inner;
+        self
+    }
+
+    pub fn with_escaping(mut self, escape: EscapeMode) -> Item {
+        self.escape = escape;
+        self
+    }
+
+    // `~` keep the new lines of the content
+    pub fn with_preserve(mut self, preserve: bool) -> Item {
+        self.preserve = preserve;
+        self
+    }
+
+    pub fn with_object_ref(mut self, object_ref: Option<(~str, Option<~str>)>) -> Item {
+        self.object_ref = object_ref;
+        self
+    }
+
+    // position of the item in the source
+    pub fn with_span(mut self, span: Span) -> Item {
+        self.span = span;
+        self
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+
+    pub fn get_tag<'a>(&'a self) -> &'a str {
+        self.tag.as_slice()
+    }
+
+    pub fn get_attributes<'a>(&'a self) -> &'a Attributes {
+        &self.attributes
+    }
+
+    pub fn get_content<'a>(&'a self) -> &'a Vec<TextPart> {
+        &self.content
+    }
+
+    // outer then inner whitespace removal
+    pub fn get_whitespace_removal(&self) -> (bool, bool) {
+        (self.remove_outer_ws, self.remove_inner_ws)
+    }
+
+    pub fn get_escaping(&self) -> EscapeMode {
+        self.escape
+    }
+
+    pub fn get_preserve(&self) -> bool {
+        self.preserve
+    }
+
+    pub fn get_object_ref<'a>(&'a self) -> &'a Option<(~str, Option<~str>)> {
+        &self.object_ref
+    }
+
+    pub fn get_expr<'a>(&'a self) -> &'a Option<Expr> {
+        &self.expr
+    }
+
+    pub fn get_vars<'a>(&'a self) -> &'a Vec<~str> {
+        &self.vars
+    }
+
+    pub fn get_locals<'a>(&'a self) -> &'a Vec<(~str, Expr)> {
+        &self.locals
+    }
+
+    pub fn get_tag_type(&self) -> TagType {
+        self.tag_type
+    }
+
     pub fn had_child(&self) -> bool {
         self.childs.len() != 0
     }
@@ -237,55 +409,159 @@ impl Item {
     }
 }
 
-fn format_attribut(attributes: &HashMap<~str, Vec<~str>>) -> ~str {
-    let mut fmt = StrBuf::new();
-    for (at, values) in attributes.iter() {
-        fmt.push_str(format!(" {}=\'", at));
-        for v in values.iter() {
-            fmt.push_str(format!("{} ", v));
-        }
-        if values.len() > 0 { unsafe { fmt.pop_byte(); } }
-        fmt.push_str("\'");
+impl DomTree {
+    pub fn render(&self, opts: &Options, ctx: &Context) -> Result<~str, HamlError> {
+        Renderer::new(opts, ctx).render(&self.root)
     }
-    fmt.into_owned()
-}
 
-fn rec_show(elt: &Item,
-            f: &mut fmt::Formatter,
-            indent: ~str) -> fmt::Result {
-    let mut res: fmt::Result = Ok(());
-    for e in elt.get_childs().iter() {
-        let f_at = format_attribut(&e.attributes);
-        match e.tag_type {
-            PlainText   => try!(write!(f.buf, "{}{}\n", indent, e.content)),
-            Inline      => try!(write!(f.buf, "{}<{}{}>{}</{}>\n", indent,
-                                     e.tag, f_at, e.content, e.tag)),
-            Block       => {
-                if e.get_childs().len() == 0 {
-                    try!(write!(f.buf, "{}<{}{}>", indent, e.tag, f_at));
-                } else {
-                    try!(write!(f.buf, "{}<{}{}>\n", indent, e.tag, f_at));
-                }
-                res = rec_show(e, f, indent + "  ");
-                if e.get_childs().len() == 0 { try!(write!(f.buf, "</{}>\n", e.tag)); }
-                else { try!(write!(f.buf, "{}</{}>\n", indent, e.tag)); }
-            },
-            HtmlComment => {
-                if e.get_childs().len() == 0 { try!(write!(f.buf, "{}<!-- ", indent)); }
-                else { try!(write!(f.buf, "{}<!--\n", indent)); }
-                if e.content != "".to_owned() { try!(write!(f.buf, "{}", e.content)); }
-                else { res = rec_show(e, f, indent + "  "); }
-                if e.get_childs().len() == 0 { try!(write!(f.buf, " -->\n")); }
-                else { try!(write!(f.buf, "{}-->\n", indent)); }
-            }
-            _           => {}
-        }
+    // the partials are loaded when they are rendered
+    pub fn render_with_loader(&self, opts: &Options, ctx: &Context,
+                              loader: &TemplateLoader) -> Result<~str, HamlError> {
+        Renderer::new(opts, ctx).with_loader(loader).render(&self.root)
     }
-    res
 }
 
 impl fmt::Show for DomTree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        rec_show(&self.root, f, "".to_owned())
+        match self.render(&Options::new(Html5), &Context::new()) {
+            Ok(html) => f.buf.write_str(html),
+            Err(e)   => write!(f.buf, "{}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use attributes::Attributes;
+    use dom_tree::{DomTree, Item, Escaped, Unescaped};
+    use format::Html5;
+    use options::Options;
+    use context::Context;
+    use interpolation;
+    use interpolation::{Literal, Interpolation};
+
+    #[test]
+    fn remove_whitespace_around_a_tag() {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::block("li".to_owned(), Attributes::new()));
+        dom_tree.insert(Item::inline("a".to_owned(), Attributes::new(),
+                                     vec!(Literal("link".to_owned())))
+                        .with_whitespace_removal(true, false));
+        assert_eq!("<li><a>link</a></li>\n".to_owned(), format!("{}", dom_tree))
+    }
+
+    #[test]
+    fn remove_whitespace_inside_a_tag() {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::block("p".to_owned(), Attributes::new())
+                        .with_whitespace_removal(false, true));
+        dom_tree.insert(Item::plain_text(vec!(Literal("text".to_owned()))));
+        dom_tree.back();
+        dom_tree.back();
+        dom_tree.insert(Item::self_closing("br".to_owned(), Attributes::new()));
+        assert_eq!("<p>text</p>\n<br>\n".to_owned(), format!("{}", dom_tree))
+    }
+
+    #[test]
+    fn keep_whitespace_without_removal() {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::block("li".to_owned(), Attributes::new()));
+        dom_tree.insert(Item::inline("a".to_owned(), Attributes::new(),
+                                     vec!(Literal("link".to_owned()))));
+        assert_eq!("<li>\n  <a>link</a>\n</li>\n".to_owned(), format!("{}", dom_tree))
+    }
+
+    #[test]
+    fn interpolate_content_and_attributes_with_context() {
+        let mut ctx = Context::new();
+        ctx.set_value("name", "John".to_owned());
+        ctx.set_value("id", "42".to_owned());
+        let mut attributes = Attributes::new();
+        attributes.merge_parts("href".to_owned(), interpolation::parse("/u/#{id}"));
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::inline("a".to_owned(), attributes,
+                                     interpolation::parse("Hello #{name}")));
+        assert_eq!("<a href='/u/42'>Hello John</a>\n".to_owned(),
+                   dom_tree.render(&Options::new(Html5), &ctx).unwrap())
+    }
+
+    #[test]
+    fn escape_content_when_escaped() {
+        let mut ctx = Context::new();
+        ctx.set_value("name", "<b>".to_owned());
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::plain_text(vec!(Interpolation("name".to_owned())))
+                        .with_escaping(Escaped));
+        dom_tree.back();
+        dom_tree.insert(Item::plain_text(vec!(Interpolation("name".to_owned())))
+                        .with_escaping(Unescaped));
+        assert_eq!("&lt;b&gt;\n<b>\n".to_owned(),
+                   dom_tree.render(&Options::new(Html5), &ctx).unwrap())
+    }
+
+    #[test]
+    fn attribute_values_are_always_escaped() {
+        let mut ctx = Context::new();
+        ctx.set_safe_value("q", "\"<b>\"".to_owned());
+        let mut attributes = Attributes::new();
+        attributes.insert("title".to_owned(), vec!("it's".to_owned()));
+        attributes.merge_parts("alt".to_owned(), vec!(Interpolation("q".to_owned())));
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::block("a".to_owned(), attributes));
+        assert_eq!("<a title='it&#39;s' alt='&quot;&lt;b&gt;&quot;'></a>\n".to_owned(),
+                   dom_tree.render(&Options::new(Html5), &ctx).unwrap())
+    }
+
+    #[test]
+    fn escape_html_option_escapes_text_except_safe_values() {
+        let mut ctx = Context::new();
+        ctx.set_value("name", "<b>".to_owned());
+        ctx.set_safe_value("html", "<i>".to_owned());
+        let mut opts = Options::new(Html5);
+        opts.escape_html = true;
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::plain_text(vec!(Literal("<p>".to_owned()),
+                                              Interpolation("name".to_owned()),
+                                              Interpolation("html".to_owned()))));
+        dom_tree.back();
+        dom_tree.insert(Item::plain_text(vec!(Interpolation("name".to_owned())))
+                        .with_escaping(Unescaped));
+        assert_eq!("<p>&lt;b&gt;<i>\n<b>\n".to_owned(), dom_tree.render(&opts, &ctx).unwrap())
+    }
+
+    #[test]
+    fn preserve_new_lines_inside_whitespace_sensitive_tags() {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::block("div".to_owned(), Attributes::new()));
+        dom_tree.insert(Item::block("pre".to_owned(), Attributes::new()));
+        dom_tree.insert(Item::plain_text(vec!(Literal("foo".to_owned()))));
+        dom_tree.back();
+        dom_tree.insert(Item::plain_text(vec!(Literal("  bar".to_owned()))));
+        assert_eq!("<div>\n  <pre>foo&#x000A;  bar</pre>\n</div>\n".to_owned(),
+                   format!("{}", dom_tree))
+    }
+
+    #[test]
+    fn preserve_new_lines_of_a_preserved_script() {
+        let mut ctx = Context::new();
+        ctx.set_safe_value("code", "a\n<pre>b\nc</pre>".to_owned());
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::inline("p".to_owned(), Attributes::new(),
+                                     vec!(Interpolation("code".to_owned())))
+                        .with_preserve(true));
+        assert_eq!("<p>a\n<pre>b&#x000A;c</pre></p>\n".to_owned(),
+                   dom_tree.render(&Options::new(Html5), &ctx).unwrap())
+    }
+
+    #[test]
+    fn preserved_script_outside_pre_keeps_its_new_lines() {
+        let mut ctx = Context::new();
+        ctx.set_value("code", "a\nb".to_owned());
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::inline("p".to_owned(), Attributes::new(),
+                                     vec!(Interpolation("code".to_owned())))
+                        .with_preserve(true));
+        assert_eq!("<p>a\nb</p>\n".to_owned(),
+                   dom_tree.render(&Options::new(Html5), &ctx).unwrap())
     }
 }
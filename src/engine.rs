@@ -22,10 +22,9 @@
 
 #![allow(unused_variable)]
 
-use std::fmt;
 use std::io::Reader;
 use std::vec::Vec;
-use std::io::IoResult;
+use std::io::{IoResult, IoError, OtherIoError};
 
 use format::{HtmlFormat, Xhtml, Html5, Html4};
 use token::*;
@@ -33,44 +32,130 @@ use lexer::Lexer;
 use parser::Parser;
 use dom_tree::DomTree;
 use input_reader::InputReader;
+use loader::TemplateLoader;
+use options::Options;
+use context::{Context, HamlObjectRef};
+use value::ToValue;
+use expr::Helper;
+use error::HamlError;
 
 pub struct Engine {
+    opts: Options,
     lexer: Lexer,
     parser: Parser,
-    dom_tree: DomTree
+    dom_tree: DomTree,
+    context: Context,
+    loader: Option<~TemplateLoader>
 }
 
 impl Engine {
     pub fn new(input: ~Reader, html_fmt: HtmlFormat) -> Engine {
         Engine {
+            opts: Options::new(html_fmt),
             lexer: Lexer::new(InputReader::new(input)),
             parser: Parser::new(html_fmt),
-            dom_tree: DomTree::new()
+            dom_tree: DomTree::new(),
+            context: Context::new(),
+            loader: None
         }
     }
 
-    pub fn execute(&mut self) -> Result<(), ~str> {
-        let tokens = self.lexer.execute();
-        println!("tokens:\n{}", tokens);
-        match self.parser.execute(tokens) {
+    pub fn execute(&mut self) -> Result<(), HamlError> {
+        let tokens = try!(self.lexer.execute());
+        self.parser = Parser::with_options(self.opts.clone());
+        match self.parser.execute_with_spans(tokens, self.lexer.get_spans()) {
             Ok(dt) => { self.dom_tree = dt; Ok(()) }
             Err(e) => Err(e)
         }
     }
 
-    pub fn set_val<T: fmt::Show>(&mut self, val: T) -> bool {
-        unimplemented!()
+    // report every error of the document, the valid lines are still generated.
+    // the lexing stops on the first error so it is the only one reported
+    pub fn execute_recovering(&mut self) -> Vec<HamlError> {
+        let tokens = match self.lexer.execute() {
+            Ok(tokens) => tokens,
+            Err(e)     => return vec!(e)
+        };
+        self.parser = Parser::with_options(self.opts.clone());
+        let (dom_tree, errors) = self.parser.execute_recovering(tokens, self.lexer.get_spans());
+        self.dom_tree = dom_tree;
+        errors
     }
 
-    pub fn set_vec_val<T: fmt::Show>(&mut self, vec_val: Vec<T>) -> bool {
-        unimplemented!()
+    // the content of these tags is not reindented and keep its new lines
+    pub fn set_preserve_tags(&mut self, tags: Vec<~str>) {
+        self.opts.preserve = tags
     }
 
-    pub fn generate(&mut self, output: &mut Writer) -> IoResult<()> {
-        // println!("html: ");
-        match output.write_str(format!("{}", self.dom_tree)) {
-            Ok(_)   => Ok(()),
-            Err(e)  => Err(e)
+    pub fn add_preserve_tag(&mut self, tag: &str) {
+        self.opts.preserve.push(tag.to_owned())
+    }
+
+    // these tags are self-closing and can't have any content
+    pub fn set_void_tags(&mut self, tags: Vec<~str>) {
+        self.opts.void = tags
+    }
+
+    pub fn add_void_tag(&mut self, tag: &str) {
+        self.opts.void.push(tag.to_owned())
+    }
+
+    // attributes are rendered in the source order unless sorted by name like Ruby Haml
+    pub fn set_sort_attributes(&mut self, sort: bool) {
+        self.opts.sort_attributes = sort
+    }
+
+    // the attribute values are always escaped, the values written in the text
+    // only with this option or `&`
+    pub fn set_escape_html(&mut self, escape: bool) {
+        self.opts.escape_html = escape
+    }
+
+    // `name.helper` or `helper(name)` in the expressions
+    pub fn register_helper(&mut self, name: &str, helper: Helper) {
+        self.opts.helpers.register(name, helper)
+    }
+
+    // the partials of `= render "name"` are loaded with it
+    pub fn set_loader(&mut self, loader: ~TemplateLoader) {
+        self.loader = Some(loader)
+    }
+
+    pub fn get_mut_context<'a>(&'a mut self) -> &'a mut Context {
+        &mut self.context
+    }
+
+    pub fn set_object_ref(&mut self, name: &str, object: ~HamlObjectRef) {
+        self.context.set_object_ref(name, object)
+    }
+
+    // the values of the engine context, used by generate
+    pub fn set_val<T: ToValue>(&mut self, key: &str, val: T) {
+        self.context.set(key, val)
+    }
+
+    pub fn set_vec_val<T: ToValue>(&mut self, key: &str, vec_val: Vec<T>) {
+        self.context.set(key, vec_val)
+    }
+
+    // render the document with the values of the given context, the same
+    // document can be rendered with many contexts
+    pub fn render(&self, ctx: &Context) -> Result<~str, HamlError> {
+        match self.loader {
+            Some(ref loader) => self.dom_tree.render_with_loader(&self.opts, ctx, &**loader),
+            None             => self.dom_tree.render(&self.opts, ctx)
         }
     }
+
+    pub fn generate(&mut self, output: &mut Writer) -> IoResult<()> {
+        let html = match self.render(&self.context) {
+            Ok(html) => html,
+            Err(e)   => return Err(IoError {
+                kind: OtherIoError,
+                desc: "can't render the template",
+                detail: Some(format!("{}", e))
+            })
+        };
+        output.write_str(html)
+    }
 }
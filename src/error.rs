@@ -20,47 +20,345 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-pub fn illegal_indent_at_begin(line: u32) -> ~str {
-    format!("line {}, indenting is forbidden at the beginning of the document.", line)
+use std::fmt;
+
+use span::Span;
+
+#[deriving(Clone, Show, Eq)]
+pub enum ErrorKind {
+    IllegalIndentAtBegin,
+    IndentUsingTabAndSpace,
+    // number of levels
+    IndentLevelMuchDeeper(u32),
+    // char and length used by the line, then by the rest of the document
+    InconsistentIndent(char, u32, char, u32),
+    InvalidTag(~str),
+    IllegalElementClassId,
+    IllegalNesting(~str),
+    IllegalPlainTextNesting,
+    NoContentOnEmptyTag,
+    UnterminatedAttributes,
+    UnterminatedString,
+    InvalidAttributeName,
+    InvalidAttributeValue,
+    InvalidAttributes,
+    InvalidObjectRef,
+    UnknownObjectRef(~str),
+    UnknownFilter(~str),
+    // name of the filter
+    TextAfterFilter(~str),
+    IllegalDoctype,
+    InvalidUtf8,
+    // the reason the input can't be read
+    ReadError(~str),
+    InvalidExpression(~str),
+    EvalError(~str),
+    UnknownSilentScript(~str),
+    // `- else` or `- elsif` without an `- if` before it
+    DanglingElse(~str),
+    BranchAfterElse(~str),
+    InvalidLoop,
+    // name of the partial and why it can't be loaded
+    PartialNotFound(~str, ~str),
+    // the partials included one in the other, the first is included again at the end
+    RecursivePartial(Vec<~str>),
+    // an error inside a partial, the span is the one of the `= render` line
+    InPartial(~str, ~HamlError)
+}
+
+fn indent_name(c: char) -> &'static str {
+    if c == ' ' { "spaces" } else { "tabs" }
+}
+
+#[deriving(Clone, Eq)]
+pub struct HamlError {
+    pub kind: ErrorKind,
+    pub span: Span
+}
+
+impl HamlError {
+    pub fn new(kind: ErrorKind, span: Span) -> HamlError {
+        HamlError { kind: kind, span: span }
+    }
+
+    // the message without the position
+    pub fn description(&self) -> ~str {
+        match self.kind {
+            IllegalIndentAtBegin => {
+                "indenting is forbidden at the beginning of the document.".to_owned()
+            },
+            IndentUsingTabAndSpace => "indentation can't use both tabs and spaces.".to_owned(),
+            IndentLevelMuchDeeper(deep_indent) => {
+                format!("was indented {} levels deeper than the previous line.", deep_indent)
+            },
+            InconsistentIndent(bad_c, bad_indent, c, indent) => {
+                format!("inconsistent indentation: {} {} used for indentation, \
+                        but the rest of the document was indented using {} {}.",
+                        bad_indent, indent_name(bad_c), indent, indent_name(c))
+            },
+            InvalidTag(ref name) => format!("invalid tag name \"{}\"", name),
+            IllegalElementClassId => {
+                "illegal element: classes and ids must have values.".to_owned()
+            },
+            IllegalNesting(ref tag_name) => {
+                format!("illegal nesting: content can't be both given on the same line as \
+                         %{} and nested within it.", tag_name)
+            },
+            IllegalPlainTextNesting => {
+                "illegal nesting: nesting within plain text is illegal".to_owned()
+            },
+            NoContentOnEmptyTag => "self-closing tags can\'t have content".to_owned(),
+            UnterminatedAttributes => {
+                "unbalanced brackets: attribute list is not closed.".to_owned()
+            },
+            UnterminatedString => "unterminated string in attribute list.".to_owned(),
+            InvalidAttributeName => "invalid attribute name.".to_owned(),
+            InvalidAttributeValue => {
+                "invalid attribute value: expected a string, a symbol, a number or a boolean."
+                    .to_owned()
+            },
+            InvalidAttributes => "invalid attribute list.".to_owned(),
+            InvalidObjectRef => {
+                "invalid object reference: expected `[object]` or `[object, :prefix]`.".to_owned()
+            },
+            UnknownObjectRef(ref name) => format!("unknown object reference \"{}\".", name),
+            UnknownFilter(ref name) => format!("filter \"{}\" is not defined.", name),
+            TextAfterFilter(ref name) => {
+                format!("text can't follow the filter \":{}\" on the same line.", name)
+            },
+            IllegalDoctype => "doctypes must be at the beginning of the document.".to_owned(),
+            InvalidUtf8 => "invalid utf-8 sequence.".to_owned(),
+            ReadError(ref msg) => format!("can't read the template: {}.", msg),
+            InvalidExpression(ref msg) => format!("invalid expression: {}.", msg),
+            EvalError(ref msg) => format!("can't evaluate expression: {}.", msg),
+            UnknownSilentScript(ref code) => format!("unknown silent script \"- {}\".", code),
+            DanglingElse(ref keyword) if keyword.as_slice() == "elsif" => {
+                "\"- elsif\" must follow an \"- if\" or an \"- elsif\".".to_owned()
+            },
+            DanglingElse(ref keyword) => {
+                format!("\"- {}\" must follow an \"- if\" or an \"- unless\".", keyword)
+            },
+            BranchAfterElse(ref keyword) => {
+                format!("\"- {}\" can't follow an \"- else\".", keyword)
+            },
+            InvalidLoop => {
+                "invalid loop: expected \"- for item in items\" or \"- items.each do |item|\"."
+                    .to_owned()
+            },
+            PartialNotFound(ref name, ref msg) => {
+                format!("can't load partial \"{}\": {}.", name, msg)
+            },
+            RecursivePartial(ref chain) => {
+                format!("recursive partial: {}.", chain.as_slice().connect(" -> "))
+            },
+            InPartial(ref name, ref e) => {
+                format!("in partial \"{}\", line {}, column {}: {}", name, e.span.line, e.span.col,
+                        e.description())
+            }
+        }
+    }
+
+    // an advice on how to fix the error
+    pub fn help(&self) -> Option<~str> {
+        match self.kind {
+            IllegalIndentAtBegin => {
+                Some("the first line of the document must not be indented".to_owned())
+            },
+            IndentUsingTabAndSpace => {
+                Some("use either tabs or spaces to indent a line, not both".to_owned())
+            },
+            IndentLevelMuchDeeper(_) => {
+                Some("a line can only be nested one level deeper than the previous one".to_owned())
+            },
+            InconsistentIndent(_, _, c, indent) => {
+                Some(format!("the document established an indentation of {} {}, \
+                             indent each level with {} {}", indent, indent_name(c), indent,
+                             indent_name(c)))
+            },
+            IllegalNesting(_) => {
+                Some("put the content on its own nested line or remove the nested lines".to_owned())
+            },
+            UnterminatedAttributes | UnterminatedString => {
+                Some("attribute lists must be closed on the same line".to_owned())
+            },
+            TextAfterFilter(_) => {
+                Some("put the content of the filter on the nested lines".to_owned())
+            },
+            InPartial(_, ref e) => e.help(),
+            DanglingElse(_) => {
+                Some("indent the branch at the same level as the \"- if\" it belongs to".to_owned())
+            },
+            _ => None
+        }
+    }
+}
+
+impl fmt::Show for HamlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f.buf, "line {}, column {}, {}", self.span.line, self.span.col, self.description())
+    }
+}
+
+pub fn illegal_indent_at_begin(span: Span) -> HamlError {
+    HamlError::new(IllegalIndentAtBegin, span)
+}
+
+pub fn indent_using_line_and_space(span: Span) -> HamlError {
+    HamlError::new(IndentUsingTabAndSpace, span)
+}
+
+pub fn indent_level_much_deeper(span: Span, deep_indent: u32) -> HamlError {
+    HamlError::new(IndentLevelMuchDeeper(deep_indent), span)
+}
+
+pub fn inconsistent_indent(span: Span, bad_c: char, c: char, bad_indent: u32,
+                           indent: u32) -> HamlError {
+    HamlError::new(InconsistentIndent(bad_c, bad_indent, c, indent), span)
+}
+
+pub fn invalid_tag(span: Span, name: ~str) -> HamlError {
+    HamlError::new(InvalidTag(name), span)
+}
+
+pub fn illegal_element_class_id(span: Span) -> HamlError {
+    HamlError::new(IllegalElementClassId, span)
+}
+
+pub fn illegal_nesting(span: Span, tag_name: ~str) -> HamlError {
+    HamlError::new(IllegalNesting(tag_name), span)
+}
+
+pub fn illegal_plain_text_nesting(span: Span) -> HamlError {
+    HamlError::new(IllegalPlainTextNesting, span)
+}
+
+pub fn no_content_on_empty_tag(span: Span) -> HamlError {
+    HamlError::new(NoContentOnEmptyTag, span)
+}
+
+pub fn unterminated_attributes(span: Span) -> HamlError {
+    HamlError::new(UnterminatedAttributes, span)
 }
 
-pub fn indent_using_line_and_space(line: u32) -> ~str {
-    format!("line {}, indentation can't use both tabs and spaces.", line)
+pub fn unterminated_string(span: Span) -> HamlError {
+    HamlError::new(UnterminatedString, span)
 }
 
-pub fn indent_level_much_deeper(line: u32, deep_indent: u32) -> ~str {
-    format!("line {}, was indented {} levels deeper than the previous line.", line, deep_indent)
+pub fn invalid_attribute_name(span: Span) -> HamlError {
+    HamlError::new(InvalidAttributeName, span)
 }
 
-pub fn inconsistent_indent(line: u32, bad_c: char, c: char, bad_indent: u32, indent: u32) -> ~str {
-    let name = if c == ' ' { "spaces" } else { "tabs" };
-    let bad_name = if bad_c == ' ' { "spaces" } else { "tabs" };
-    format!("line {}, inconsistent indentation: {} {} used for indentation, \
-            but the rest of the document was indented using {} {}.",
-            line,
-            bad_indent,
-            bad_name,
-            indent,
-            name )
+pub fn invalid_attribute_value(span: Span) -> HamlError {
+    HamlError::new(InvalidAttributeValue, span)
 }
 
-pub fn invalid_tag(line: u32, name: ~str) -> ~str {
-    format!("line {}, invalid tag name \"{}\"", line, name)
+pub fn invalid_attributes(span: Span) -> HamlError {
+    HamlError::new(InvalidAttributes, span)
 }
 
-pub fn illegal_element_class_id(line: u32) -> ~str {
-    format!("line {}, illegal element: classes and ids must have values.", line)
+pub fn invalid_object_ref(span: Span) -> HamlError {
+    HamlError::new(InvalidObjectRef, span)
 }
 
-pub fn illegal_nesting(line: u32, tag_name: ~str) -> ~str {
-    format!("line {}, illegal nesting: content can't be both given on the same line as \
-             %{} and nested within it.", line, tag_name)
+pub fn unknown_object_ref(span: Span, name: ~str) -> HamlError {
+    HamlError::new(UnknownObjectRef(name), span)
 }
 
-pub fn illegal_plain_text_nesting(line: u32) -> ~str {
-    format!("line {}, illegal nesting: nesting within plain text is illegal", line)
+pub fn unknown_filter(span: Span, name: ~str) -> HamlError {
+    HamlError::new(UnknownFilter(name), span)
 }
 
-pub fn no_content_on_empty_tag(line: u32) -> ~str {
-    format!("line {}, self-closing tags can\'t have content", line)
+pub fn text_after_filter(span: Span, name: ~str) -> HamlError {
+    HamlError::new(TextAfterFilter(name), span)
+}
+
+pub fn illegal_doctype(span: Span) -> HamlError {
+    HamlError::new(IllegalDoctype, span)
+}
+
+pub fn invalid_utf8(span: Span) -> HamlError {
+    HamlError::new(InvalidUtf8, span)
+}
+
+pub fn read_error(span: Span, msg: ~str) -> HamlError {
+    HamlError::new(ReadError(msg), span)
+}
+
+pub fn invalid_expression(span: Span, msg: ~str) -> HamlError {
+    HamlError::new(InvalidExpression(msg), span)
+}
+
+pub fn eval_error(span: Span, msg: ~str) -> HamlError {
+    HamlError::new(EvalError(msg), span)
+}
+
+pub fn unknown_silent_script(span: Span, code: ~str) -> HamlError {
+    HamlError::new(UnknownSilentScript(code), span)
+}
+
+pub fn dangling_else(span: Span, keyword: ~str) -> HamlError {
+    HamlError::new(DanglingElse(keyword), span)
+}
+
+pub fn branch_after_else(span: Span, keyword: ~str) -> HamlError {
+    HamlError::new(BranchAfterElse(keyword), span)
+}
+
+pub fn invalid_loop(span: Span) -> HamlError {
+    HamlError::new(InvalidLoop, span)
+}
+
+pub fn partial_not_found(span: Span, name: ~str, msg: ~str) -> HamlError {
+    HamlError::new(PartialNotFound(name, msg), span)
+}
+
+pub fn recursive_partial(span: Span, chain: Vec<~str>) -> HamlError {
+    HamlError::new(RecursivePartial(chain), span)
+}
+
+pub fn in_partial(span: Span, name: ~str, e: HamlError) -> HamlError {
+    HamlError::new(InPartial(name, ~e), span)
+}
+
+#[cfg(test)]
+mod test {
+    use error::{HamlError, InconsistentIndent, UnknownFilter, InPartial, IllegalElementClassId,
+                InvalidAttributeName};
+    use span::Span;
+
+    #[test]
+    fn error_message_contains_the_position() {
+        let e = HamlError::new(UnknownFilter("foo".to_owned()), Span::new(3, 5, 20, 4));
+        assert_eq!("line 3, column 5, filter \"foo\" is not defined.".to_owned(), format!("{}", e))
+    }
+
+    #[test]
+    fn inconsistent_indent_message() {
+        let e = HamlError::new(InconsistentIndent('\t', 1, ' ', 2), Span::line(2));
+        assert_eq!("inconsistent indentation: 1 tabs used for indentation, \
+                    but the rest of the document was indented using 2 spaces.".to_owned(),
+                   e.description())
+    }
+
+    #[test]
+    fn inconsistent_indent_help_gives_the_document_indentation() {
+        let e = HamlError::new(InconsistentIndent('\t', 1, ' ', 2), Span::line(2));
+        assert_eq!(Some("the document established an indentation of 2 spaces, \
+                         indent each level with 2 spaces".to_owned()), e.help())
+    }
+
+    #[test]
+    fn invalid_attribute_name_message_fits_both_attribute_syntaxes() {
+        let e = HamlError::new(InvalidAttributeName, Span::line(1));
+        assert_eq!("invalid attribute name.".to_owned(), e.description())
+    }
+
+    #[test]
+    fn partial_error_message_contains_the_inner_position() {
+        let inner = HamlError::new(IllegalElementClassId, Span::line(2));
+        let e = HamlError::new(InPartial("card".to_owned(), ~inner), Span::line(4));
+        assert_eq!("line 4, column 1, in partial \"card\", line 2, column 1: \
+                    illegal element: classes and ids must have values.".to_owned(),
+                   format!("{}", e))
+    }
 }
@@ -0,0 +1,685 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::str;
+use std::num::{CheckedAdd, CheckedSub, CheckedMul, CheckedDiv};
+use std::ascii::StrAsciiExt;
+use collections::HashMap;
+
+use value::{Value, Nil, Bool, Int, Float, Str, Safe, List, Map};
+
+// the expressions of the `=` lines and of the `#{}` interpolations
+
+// the variables which can be used in an expression
+pub trait Lookup {
+    fn lookup_var(&self, name: &str) -> Option<Value>;
+}
+
+#[deriving(Clone, Show, Eq)]
+pub enum BinOp {
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Lower,
+    LowerOrEqual,
+    Greater,
+    GreaterOrEqual,
+    And,
+    Or
+}
+
+#[deriving(Clone, Show, Eq)]
+pub enum Expr {
+    Lit(Value),
+    Var(~str),
+    // `a.b`, a map field or a helper called without arguments
+    Field(~Expr, ~str),
+    Index(~Expr, ~Expr),
+    // `helper(args)` or `receiver.helper(args)`, the receiver is the first argument
+    Call(~str, Vec<Expr>),
+    Not(~Expr),
+    Negative(~Expr),
+    Binary(BinOp, ~Expr, ~Expr)
+}
+
+#[deriving(Clone, Eq)]
+enum ExprToken {
+    Ident(~str),
+    Number(Value),
+    StrLit(~str),
+    Punct(~str)
+}
+
+impl fmt::Show for ExprToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ident(ref s) | Punct(ref s) => f.buf.write_str(s.as_slice()),
+            Number(ref v)               => f.buf.write_str(v.to_output()),
+            StrLit(ref s)               => write!(f.buf, "\"{}\"", s)
+        }
+    }
+}
+
+static punctuations: [&'static str, ..15] = ["+", "-", "*", "/", "%", "<", ">", "!", ".", ",",
+                                             ":", "(", ")", "[", "]"];
+static double_punctuations: [&'static str, ..6] = ["==", "!=", "<=", ">=", "&&", "||"];
+
+fn tokenize(source: &str) -> Result<Vec<ExprToken>, ~str> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = *chars.get(i);
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_digit_radix(10) {
+            // only ascii digits, the other unicode digits can't be parsed as numbers
+            let start = i;
+            while i < chars.len() && chars.get(i).is_digit_radix(10) { i += 1; }
+            // a dot followed by a digit is a decimal point, otherwise a field access
+            if i + 1 < chars.len() && *chars.get(i) == '.' && chars.get(i + 1).is_digit_radix(10) {
+                i += 1;
+                while i < chars.len() && chars.get(i).is_digit_radix(10) { i += 1; }
+                let text = str::from_chars(chars.slice(start, i));
+                match from_str(text) {
+                    Some(f) => tokens.push(Number(Float(f))),
+                    None    => return Err(format!("invalid number {}", text))
+                }
+            } else {
+                let text = str::from_chars(chars.slice(start, i));
+                match from_str(text) {
+                    Some(n) => tokens.push(Number(Int(n))),
+                    None    => return Err(format!("number {} is too big", text))
+                }
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars.get(i).is_alphanumeric() || *chars.get(i) == '_') {
+                i += 1;
+            }
+            tokens.push(Ident(str::from_chars(chars.slice(start, i))));
+        } else if c == '"' || c == '\'' {
+            let mut text = StrBuf::new();
+            i += 1;
+            loop {
+                if i >= chars.len() { return Err("unterminated string".to_owned()) }
+                let ch = *chars.get(i);
+                i += 1;
+                if ch == c { break }
+                if ch == '\\' && i < chars.len() {
+                    text.push_char(match *chars.get(i) {
+                        'n' => '\n',
+                        't' => '\t',
+                        e   => e
+                    });
+                    i += 1;
+                } else {
+                    text.push_char(ch);
+                }
+            }
+            tokens.push(StrLit(text.into_owned()));
+        } else {
+            let double = if i + 1 < chars.len() { str::from_chars([c, *chars.get(i + 1)]) }
+                         else { "".to_owned() };
+            if double_punctuations.iter().any(|p| *p == double.as_slice()) {
+                tokens.push(Punct(double));
+                i += 2;
+            } else if punctuations.iter().any(|p| *p == str::from_char(c).as_slice()) {
+                tokens.push(Punct(str::from_char(c)));
+                i += 1;
+            } else {
+                return Err(format!("unexpected character `{}`", c))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<ExprToken>,
+    pos: uint
+}
+
+impl ExprParser {
+    fn peek<'a>(&'a self) -> Option<&'a ExprToken> {
+        if self.pos < self.tokens.len() { Some(self.tokens.get(self.pos)) } else { None }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = match self.peek() {
+            Some(&Punct(ref p)) => p.as_slice() == punct,
+            _                   => false
+        };
+        if found { self.pos += 1; }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ~str> {
+        if self.eat(punct) { Ok(()) } else { Err(self.unexpected()) }
+    }
+
+    fn unexpected(&self) -> ~str {
+        match self.peek() {
+            Some(token) => format!("unexpected `{}`", token),
+            None        => "unexpected end of expression".to_owned()
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ~str> {
+        let mut left = try!(self.parse_and());
+        while self.eat("||") {
+            let right = try!(self.parse_and());
+            left = Binary(Or, ~left, ~right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ~str> {
+        let mut left = try!(self.parse_comparison());
+        while self.eat("&&") {
+            let right = try!(self.parse_comparison());
+            left = Binary(And, ~left, ~right);
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ~str> {
+        let left = try!(self.parse_additive());
+        let op = if self.eat("==") { Equal }
+                 else if self.eat("!=") { NotEqual }
+                 else if self.eat("<=") { LowerOrEqual }
+                 else if self.eat(">=") { GreaterOrEqual }
+                 else if self.eat("<") { Lower }
+                 else if self.eat(">") { Greater }
+                 else { return Ok(left) };
+        let right = try!(self.parse_additive());
+        Ok(Binary(op, ~left, ~right))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ~str> {
+        let mut left = try!(self.parse_multiplicative());
+        loop {
+            let op = if self.eat("+") { Plus } else if self.eat("-") { Minus } else { break };
+            let right = try!(self.parse_multiplicative());
+            left = Binary(op, ~left, ~right);
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ~str> {
+        let mut left = try!(self.parse_unary());
+        loop {
+            let op = if self.eat("*") { Times }
+                     else if self.eat("/") { Divide }
+                     else if self.eat("%") { Modulo }
+                     else { break };
+            let right = try!(self.parse_unary());
+            left = Binary(op, ~left, ~right);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ~str> {
+        if self.eat("!") { Ok(Not(~try!(self.parse_unary()))) }
+        else if self.eat("-") { Ok(Negative(~try!(self.parse_unary()))) }
+        else { self.parse_postfix() }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ~str> {
+        let mut expr = try!(self.parse_primary());
+        loop {
+            if self.eat(".") {
+                // `list.0` is the same as `list[0]`
+                let name = match self.peek() {
+                    Some(&Ident(ref name))     => name.clone(),
+                    Some(&Number(Int(index)))  => index.to_str(),
+                    _                          => return Err(self.unexpected())
+                };
+                self.pos += 1;
+                if self.eat("(") {
+                    let mut args = vec!(expr);
+                    args.push_all_move(try!(self.parse_arguments()));
+                    expr = Call(name, args);
+                } else {
+                    expr = Field(~expr, name);
+                }
+            } else if self.eat("[") {
+                let index = try!(self.parse_or());
+                try!(self.expect("]"));
+                expr = Index(~expr, ~index);
+            } else {
+                return Ok(expr)
+            }
+        }
+    }
+
+    // the arguments after the opening parenthesis
+    fn parse_arguments(&mut self) -> Result<Vec<Expr>, ~str> {
+        let mut args = Vec::new();
+        if self.eat(")") { return Ok(args) }
+        loop {
+            args.push(try!(self.parse_or()));
+            if !self.eat(",") { break }
+        }
+        try!(self.expect(")"));
+        Ok(args)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ~str> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None        => return Err(self.unexpected())
+        };
+        match token {
+            Number(value)              => { self.pos += 1; Ok(Lit(value)) },
+            StrLit(s)                  => { self.pos += 1; Ok(Lit(Str(s))) },
+            Ident(name)                => {
+                self.pos += 1;
+                match name.as_slice() {
+                    "true"  => Ok(Lit(Bool(true))),
+                    "false" => Ok(Lit(Bool(false))),
+                    "nil"   => Ok(Lit(Nil)),
+                    _       => {
+                        if self.eat("(") { Ok(Call(name, try!(self.parse_arguments()))) }
+                        else { Ok(Var(name)) }
+                    }
+                }
+            },
+            Punct(ref p) if p.as_slice() == "(" => {
+                self.pos += 1;
+                let expr = try!(self.parse_or());
+                try!(self.expect(")"));
+                Ok(expr)
+            },
+            _                          => Err(self.unexpected())
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Expr, ~str> {
+    let mut parser = ExprParser { tokens: try!(tokenize(source)), pos: 0 };
+    let expr = try!(parser.parse_or());
+    if parser.pos < parser.tokens.len() { return Err(parser.unexpected()) }
+    Ok(expr)
+}
+
+// `"name", key: value, ...`, the name of a partial and its locals
+pub fn parse_partial(source: &str) -> Result<(~str, Vec<(~str, Expr)>), ~str> {
+    let mut parser = ExprParser { tokens: try!(tokenize(source)), pos: 0 };
+    let name = match parser.peek() {
+        Some(&StrLit(ref name)) => name.clone(),
+        _                       => return Err(parser.unexpected())
+    };
+    parser.pos += 1;
+    let mut locals = Vec::new();
+    while parser.eat(",") {
+        let key = match parser.peek() {
+            Some(&Ident(ref key)) => key.clone(),
+            _                     => return Err(parser.unexpected())
+        };
+        parser.pos += 1;
+        try!(parser.expect(":"));
+        locals.push((key, try!(parser.parse_or())));
+    }
+    if parser.pos < parser.tokens.len() { return Err(parser.unexpected()) }
+    Ok((name, locals))
+}
+
+// a function callable from the templates, `name.helper` and `helper(name)`
+// are the same call
+pub type Helper = fn(&[Value]) -> Result<Value, ~str>;
+
+pub struct Helpers {
+    helpers: HashMap<~str, Helper>
+}
+
+impl Helpers {
+    // the built-in helpers
+    pub fn new() -> Helpers {
+        let mut helpers = Helpers { helpers: HashMap::new() };
+        helpers.register("length", length);
+        helpers.register("upcase", upcase);
+        helpers.register("downcase", downcase);
+        helpers.register("join", join);
+        helpers
+    }
+
+    pub fn register(&mut self, name: &str, helper: Helper) {
+        self.helpers.insert(name.to_owned(), helper);
+    }
+
+    pub fn find(&self, name: &str) -> Option<Helper> {
+        self.helpers.find(&name.to_owned()).map(|h| *h)
+    }
+}
+
+impl Clone for Helpers {
+    fn clone(&self) -> Helpers {
+        let mut helpers = HashMap::new();
+        for (name, helper) in self.helpers.iter() { helpers.insert(name.clone(), *helper); }
+        Helpers { helpers: helpers }
+    }
+}
+
+fn one_argument<'a>(name: &str, args: &'a [Value]) -> Result<&'a Value, ~str> {
+    if args.len() == 1 { Ok(&args[0]) }
+    else { Err(format!("{} expects 1 argument, {} given", name, args.len())) }
+}
+
+fn length(args: &[Value]) -> Result<Value, ~str> {
+    match *try!(one_argument("length", args)) {
+        Str(ref s) | Safe(ref s) => Ok(Int(s.char_len() as i64)),
+        List(ref values)         => Ok(Int(values.len() as i64)),
+        Map(ref map)             => Ok(Int(map.len() as i64)),
+        ref v                    => Err(format!("{} has no length", v))
+    }
+}
+
+fn upcase(args: &[Value]) -> Result<Value, ~str> {
+    match *try!(one_argument("upcase", args)) {
+        Safe(ref s) => Ok(Safe(s.to_ascii_upper())),
+        ref v       => Ok(Str(v.to_output().to_ascii_upper()))
+    }
+}
+
+fn downcase(args: &[Value]) -> Result<Value, ~str> {
+    match *try!(one_argument("downcase", args)) {
+        Safe(ref s) => Ok(Safe(s.to_ascii_lower())),
+        ref v       => Ok(Str(v.to_output().to_ascii_lower()))
+    }
+}
+
+// `list.join` or `list.join(", ")`
+fn join(args: &[Value]) -> Result<Value, ~str> {
+    let separator = match args.get(1) {
+        Some(sep) => sep.to_output(),
+        None      => "".to_owned()
+    };
+    match args.get(0) {
+        Some(&List(ref values)) if args.len() <= 2 => {
+            let values: Vec<~str> = values.iter().map(|v| v.to_output()).collect();
+            Ok(Str(values.as_slice().connect(separator.as_slice())))
+        },
+        _                                          => {
+            Err("join expects a list and an optional separator".to_owned())
+        }
+    }
+}
+
+fn is_text(value: &Value) -> bool {
+    match *value {
+        Str(_) | Safe(_) => true,
+        _                => false
+    }
+}
+
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (&Int(a), &Float(b))            => a as f64 == b,
+        (&Float(a), &Int(b))            => a == b as f64,
+        _ if is_text(left) && is_text(right) => left.to_output() == right.to_output(),
+        _                               => left == right
+    }
+}
+
+fn int_op(op: BinOp, a: i64, b: i64) -> Result<Value, ~str> {
+    match op {
+        Divide | Modulo if b == 0 => Err("divided by 0".to_owned()),
+        Plus           => checked(a.checked_add(&b)),
+        Minus          => checked(a.checked_sub(&b)),
+        Times          => checked(a.checked_mul(&b)),
+        Divide         => checked(a.checked_div(&b)),
+        // the remainder overflows exactly when the division does
+        Modulo         => checked(a.checked_div(&b).map(|_| a % b)),
+        _              => float_op(op, a as f64, b as f64)
+    }
+}
+
+fn checked(res: Option<i64>) -> Result<Value, ~str> {
+    match res {
+        Some(i) => Ok(Int(i)),
+        None    => Err("integer overflow".to_owned())
+    }
+}
+
+fn float_op(op: BinOp, a: f64, b: f64) -> Result<Value, ~str> {
+    match op {
+        Plus           => Ok(Float(a + b)),
+        Minus          => Ok(Float(a - b)),
+        Times          => Ok(Float(a * b)),
+        Divide         => Ok(Float(a / b)),
+        Modulo         => Ok(Float(a % b)),
+        Lower          => Ok(Bool(a < b)),
+        LowerOrEqual   => Ok(Bool(a <= b)),
+        Greater        => Ok(Bool(a > b)),
+        GreaterOrEqual => Ok(Bool(a >= b)),
+        _              => Err(format!("unsupported operator {}", op))
+    }
+}
+
+fn apply(op: BinOp, left: &Value, right: &Value) -> Result<Value, ~str> {
+    match (op, left, right) {
+        (Equal, _, _)                      => Ok(Bool(equals(left, right))),
+        (NotEqual, _, _)                   => Ok(Bool(!equals(left, right))),
+        (_, &Int(a), &Int(b))              => int_op(op, a, b),
+        (_, &Int(a), &Float(b))            => float_op(op, a as f64, b),
+        (_, &Float(a), &Int(b))            => float_op(op, a, b as f64),
+        (_, &Float(a), &Float(b))          => float_op(op, a, b),
+        (Plus, &List(ref a), &List(ref b)) => {
+            let mut values = a.clone();
+            values.push_all(b.as_slice());
+            Ok(List(values))
+        },
+        _ if is_text(left) && is_text(right) => {
+            let (a, b) = (left.to_output(), right.to_output());
+            match op {
+                Plus           => Ok(Str(format!("{}{}", a, b))),
+                Lower          => Ok(Bool(a < b)),
+                LowerOrEqual   => Ok(Bool(a <= b)),
+                Greater        => Ok(Bool(a > b)),
+                GreaterOrEqual => Ok(Bool(a >= b)),
+                _              => Err(format!("unsupported operator {} for strings", op))
+            }
+        },
+        _                                  => {
+            Err(format!("unsupported operator {} for {} and {}", op, left, right))
+        }
+    }
+}
+
+// an unknown variable is nil, an unknown helper is an error
+pub fn eval(expr: &Expr, vars: &Lookup, helpers: &Helpers) -> Result<Value, ~str> {
+    match *expr {
+        Lit(ref value)          => Ok(value.clone()),
+        Var(ref name)           => Ok(vars.lookup_var(name.as_slice()).unwrap_or(Nil)),
+        Field(ref e, ref name)  => {
+            let value = try!(eval(&**e, vars, helpers));
+            match value.get(name.as_slice()) {
+                Some(v) => Ok(v),
+                None    => match (helpers.find(name.as_slice()), &value) {
+                    (Some(helper), _)               => helper(&[value.clone()]),
+                    (None, &Nil) | (None, &Map(_))
+                    | (None, &List(_))              => Ok(Nil),
+                    (None, _)                       => {
+                        Err(format!("{} has no field or helper `{}`", value, name))
+                    }
+                }
+            }
+        },
+        Index(ref e, ref index) => {
+            let value = try!(eval(&**e, vars, helpers));
+            let index = try!(eval(&**index, vars, helpers));
+            match (&value, &index) {
+                (&List(ref values), &Int(i)) => {
+                    // a negative index starts from the end
+                    let i = if i < 0 { values.len() as i64 + i } else { i };
+                    if i >= 0 && i < values.len() as i64 { Ok(values.get(i as uint).clone()) }
+                    else { Ok(Nil) }
+                },
+                (&Map(_), _) | (&Nil, _)     => Ok(value.get(index.to_output()).unwrap_or(Nil)),
+                _                            => Err(format!("{} can't be indexed by {}",
+                                                            value, index))
+            }
+        },
+        Call(ref name, ref args) => {
+            let helper = match helpers.find(name.as_slice()) {
+                Some(helper) => helper,
+                None         => return Err(format!("unknown helper `{}`", name))
+            };
+            let mut values = Vec::new();
+            for arg in args.iter() { values.push(try!(eval(arg, vars, helpers))); }
+            helper(values.as_slice())
+        },
+        Not(ref e)               => Ok(Bool(!try!(eval(&**e, vars, helpers)).is_true())),
+        Negative(ref e)          => {
+            match try!(eval(&**e, vars, helpers)) {
+                Int(i)   => checked(0i64.checked_sub(&i)),
+                Float(f) => Ok(Float(-f)),
+                v        => Err(format!("{} can't be negative", v))
+            }
+        },
+        Binary(And, ref l, ref r) => {
+            let left = try!(eval(&**l, vars, helpers));
+            if left.is_true() { eval(&**r, vars, helpers) } else { Ok(left) }
+        },
+        Binary(Or, ref l, ref r)  => {
+            let left = try!(eval(&**l, vars, helpers));
+            if left.is_true() { Ok(left) } else { eval(&**r, vars, helpers) }
+        },
+        Binary(op, ref l, ref r)  => {
+            let left = try!(eval(&**l, vars, helpers));
+            let right = try!(eval(&**r, vars, helpers));
+            apply(op, &left, &right)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::i64;
+    use context::Context;
+    use value::{Value, Nil, Bool, Int, Float, Str};
+    use expr::{parse, parse_partial, eval, Helpers, Lit, Var, Field, Call, Binary, Plus, Times};
+
+    fn eval_str(source: &str, ctx: &Context) -> Result<Value, ~str> {
+        eval(&parse(source).unwrap(), ctx, &Helpers::new())
+    }
+
+    #[test]
+    fn parse_operators_by_precedence() {
+        assert_eq!(Ok(Binary(Plus, ~Var("a".to_owned()),
+                             ~Binary(Times, ~Var("b".to_owned()), ~Var("c".to_owned())))),
+                   parse("a + b * c"))
+    }
+
+    #[test]
+    fn parse_field_and_helper_calls() {
+        assert_eq!(Ok(Call("join".to_owned(), vec!(Field(~Var("user".to_owned()),
+                                                         "tags".to_owned()),
+                                                   Var("sep".to_owned())))),
+                   parse("user.tags.join(sep)"))
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(Err("unexpected end of expression".to_owned()), parse("a +"))
+        assert_eq!(Err("unexpected `)`".to_owned()), parse("(a))"))
+        assert_eq!(Err("unterminated string".to_owned()), parse("'abc"))
+        assert!(parse("a $ b").is_err())
+    }
+
+    #[test]
+    fn parse_partial_name_and_locals() {
+        assert_eq!(Ok(("card".to_owned(), vec!(("title".to_owned(), Var("t".to_owned())),
+                                              ("size".to_owned(), Lit(Int(2)))))),
+                   parse_partial("\"card\", title: t, size: 2"))
+        assert!(parse_partial("card").is_err())
+        assert!(parse_partial("\"card\", title t").is_err())
+    }
+
+    #[test]
+    fn eval_literals_and_arithmetic() {
+        let ctx = Context::new();
+        assert_eq!(Ok(Int(7)), eval_str("1 + 2 * 3", &ctx))
+        assert_eq!(Ok(Float(2.5)), eval_str("5 / 2.0", &ctx))
+        assert_eq!(Ok(Str("ab".to_owned())), eval_str("'a' + \"b\"", &ctx))
+        assert_eq!(Ok(Bool(true)), eval_str("!(1 > 2) && 2 >= 2", &ctx))
+        assert!(eval_str("1 / 0", &ctx).is_err())
+    }
+
+    #[test]
+    fn eval_integer_overflow() {
+        let mut ctx = Context::new();
+        ctx.set("max", i64::MAX);
+        ctx.set("min", i64::MIN);
+        let overflows = |source: &str| eval_str(source, &ctx) == Err("integer overflow".to_owned());
+        assert!(overflows("max + 1"))
+        assert!(overflows("min - 1"))
+        assert!(overflows("max * 2"))
+        assert!(overflows("min / -1"))
+        assert!(overflows("min % -1"))
+        assert!(overflows("-min"))
+    }
+
+    #[test]
+    fn non_ascii_digits_are_not_numbers() {
+        assert!(parse("\u0663.\u0663").is_err())
+        assert!(parse("\u0663").is_err())
+    }
+
+    #[test]
+    fn eval_lookup_and_index() {
+        let mut ctx = Context::new();
+        ctx.set("names", vec!("a", "b", "c"));
+        assert_eq!(Ok(Str("b".to_owned())), eval_str("names[1]", &ctx))
+        assert_eq!(Ok(Str("c".to_owned())), eval_str("names[-1]", &ctx))
+        assert_eq!(Ok(Str("a".to_owned())), eval_str("names.0", &ctx))
+        assert_eq!(Ok(Nil), eval_str("unknown.field", &ctx))
+    }
+
+    #[test]
+    fn eval_helpers() {
+        let mut ctx = Context::new();
+        ctx.set("names", vec!("a", "b"));
+        assert_eq!(Ok(Int(2)), eval_str("names.length", &ctx))
+        assert_eq!(Ok(Str("A, B".to_owned())), eval_str("upcase(names.join(', '))", &ctx))
+        assert!(eval_str("unknown(names)", &ctx).is_err())
+    }
+
+    #[test]
+    fn eval_registered_helper() {
+        fn double(args: &[Value]) -> Result<Value, ~str> {
+            match args.get(0) {
+                Some(&Int(i)) => Ok(Int(i * 2)),
+                _             => Err("double expects a number".to_owned())
+            }
+        }
+        let mut helpers = Helpers::new();
+        helpers.register("double", double);
+        assert_eq!(Ok(Int(4)), eval(&parse("2.double").unwrap(), &Context::new(), &helpers))
+    }
+}
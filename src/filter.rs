@@ -0,0 +1,193 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use format::{HtmlFormat, Xhtml, Html4, Html5};
+use escape::escape_html;
+
+static filters: [&'static str, ..6] = ["plain", "javascript", "css", "cdata", "escaped",
+                                       "preserve"];
+
+pub fn is_filter(name: &str) -> bool {
+    filters.iter().any(|&f| f == name)
+}
+
+pub fn render(name: &str, content: &str, html_fmt: HtmlFormat, indent: &str) -> ~str {
+    let nested = indent + "  ";
+    match name {
+        "javascript" => {
+            match html_fmt {
+                Xhtml => format!("{}<script type='text/javascript'>\n{}  //<![CDATA[\n{}{}  //]]>\n\
+                                  {}</script>\n", indent, indent,
+                                 indent_lines(content, nested + "  "), indent, indent),
+                Html4 => format!("{}<script type='text/javascript'>\n{}{}</script>\n", indent,
+                                 indent_lines(content, nested), indent),
+                Html5 => format!("{}<script>\n{}{}</script>\n", indent,
+                                 indent_lines(content, nested), indent)
+            }
+        },
+        "css"        => {
+            match html_fmt {
+                Xhtml => format!("{}<style type='text/css'>\n{}  /*<![CDATA[*/\n{}{}  /*]]>*/\n\
+                                  {}</style>\n", indent, indent,
+                                 indent_lines(content, nested + "  "), indent, indent),
+                Html4 => format!("{}<style type='text/css'>\n{}{}</style>\n", indent,
+                                 indent_lines(content, nested), indent),
+                Html5 => format!("{}<style>\n{}{}</style>\n", indent,
+                                 indent_lines(content, nested), indent)
+            }
+        },
+        "cdata"      => format!("{}<![CDATA[\n{}{}]]>\n", indent,
+                                indent_lines(content, nested), indent),
+        "escaped"    => indent_lines(escape_html(content), indent),
+        "preserve"   => format!("{}{}\n", indent, preserve(content)),
+        _            => indent_lines(content, indent)
+    }
+}
+
+// encode the new lines so the content is not reindented
+pub fn preserve(content: &str) -> ~str {
+    content.replace("\n", "&#x000A;")
+}
+
+// position of the first `<tag>` or `<tag ...>` of the content
+fn find_open_tag(content: &str, tag: &str) -> Option<uint> {
+    let open = format!("<{}", tag);
+    let mut from = 0;
+    loop {
+        match content.slice_from(from).find_str(open.as_slice()) {
+            Some(i) => {
+                let end = from + i + open.len();
+                match content.slice_from(end).chars().next() {
+                    Some('>') | Some(' ') => return Some(from + i),
+                    Some(_)               => from = end,
+                    None                  => return None
+                }
+            },
+            None    => return None
+        }
+    }
+}
+
+// `~` only encode the new lines inside the whitespace sensitive tags of the content
+pub fn find_and_preserve(content: &str, tags: &[~str]) -> ~str {
+    let mut res = StrBuf::new();
+    let mut rest = content;
+    loop {
+        let first = tags.iter().filter_map(|t| {
+            find_open_tag(rest, t.as_slice()).map(|i| (i, t.clone()))
+        }).min_by(|&(i, _)| i);
+        match first {
+            Some((start, tag)) => {
+                let close = format!("</{}>", tag);
+                let end = match rest.slice_from(start).find_str(close.as_slice()) {
+                    Some(i) => start + i + close.len(),
+                    None    => rest.len()
+                };
+                res.push_str(rest.slice_to(start));
+                res.push_str(preserve(rest.slice(start, end)));
+                rest = rest.slice_from(end);
+            },
+            None               => {
+                res.push_str(rest);
+                return res.into_owned()
+            }
+        }
+    }
+}
+
+fn indent_lines(content: &str, indent: &str) -> ~str {
+    let mut res = StrBuf::new();
+    for line in content.lines() {
+        if line.len() > 0 {
+            res.push_str(indent);
+            res.push_str(line);
+        }
+        res.push_char('\n');
+    }
+    res.into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use format::{Xhtml, Html4, Html5};
+    use filter::{is_filter, render, find_and_preserve};
+
+    #[test]
+    fn builtin_filters_are_known() {
+        assert_true!(is_filter("javascript"))
+        assert_true!(is_filter("preserve"))
+        assert_false!(is_filter("ruby"))
+    }
+
+    #[test]
+    fn plain_filter_keep_content() {
+        assert_eq!("  a\n\n  <b>\n".to_owned(), render("plain", "a\n\n<b>", Html5, "  "))
+    }
+
+    #[test]
+    fn javascript_filter_in_html5() {
+        assert_eq!("<script>\n  var a;\n</script>\n".to_owned(),
+                   render("javascript", "var a;", Html5, ""))
+    }
+
+    #[test]
+    fn javascript_filter_in_html4() {
+        assert_eq!("<script type='text/javascript'>\n  var a;\n</script>\n".to_owned(),
+                   render("javascript", "var a;", Html4, ""))
+    }
+
+    #[test]
+    fn javascript_filter_in_xhtml_add_cdata() {
+        assert_eq!("<script type='text/javascript'>\n  //<![CDATA[\n    var a;\n  //]]>\n\
+                    </script>\n".to_owned(),
+                   render("javascript", "var a;", Xhtml, ""))
+    }
+
+    #[test]
+    fn css_filter_in_xhtml_add_cdata() {
+        assert_eq!("<style type='text/css'>\n  /*<![CDATA[*/\n    p {}\n  /*]]>*/\n</style>\n"
+                   .to_owned(), render("css", "p {}", Xhtml, ""))
+    }
+
+    #[test]
+    fn cdata_filter() {
+        assert_eq!("<![CDATA[\n  a\n]]>\n".to_owned(), render("cdata", "a", Html5, ""))
+    }
+
+    #[test]
+    fn escaped_filter_escape_html() {
+        assert_eq!("  &lt;b&gt;\n".to_owned(), render("escaped", "<b>", Html5, "  "))
+    }
+
+    #[test]
+    fn preserve_filter_encode_new_lines() {
+        assert_eq!("  a&#x000A;  b\n".to_owned(), render("preserve", "a\n  b", Html5, "  "))
+    }
+
+    #[test]
+    fn find_and_preserve_only_encode_whitespace_sensitive_tags() {
+        let tags = vec!("pre".to_owned(), "textarea".to_owned());
+        assert_eq!("a\n<pre class='x'>b&#x000A;c</pre>\n<prefix>\nd".to_owned(),
+                   find_and_preserve("a\n<pre class='x'>b\nc</pre>\n<prefix>\nd",
+                                     tags.as_slice()))
+    }
+}
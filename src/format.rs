@@ -64,6 +64,7 @@ pub static HTML_4_01_Frameset: &'static str =
 "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Frameset//EN\" \
 \"http://www.w3.org/TR/html4/frameset.dtd\">";
 
+#[deriving(Clone, Eq, Show)]
 pub enum HtmlFormat {
     Xhtml,
     Html4,
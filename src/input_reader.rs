@@ -20,13 +20,28 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::io::Reader;
+use std::io::{Reader, EndOfFile};
 use std::vec::Vec;
+use std::str;
+
+use error;
+use error::HamlError;
+use span::{Position, Span};
+
+// byte order mark, skipped when found at the beginning of the input
+static BOM: char = '\uFEFF';
+// number of positions kept to restore the position of ungot chars,
+// the lexer can give back a whole line
+static HISTORY_LEN: uint = 4096;
 
 pub struct InputReader {
     input: ~Reader,
     buffer: Vec<char>,
-    eof: bool
+    eof: bool,
+    started: bool,
+    pos: Position,
+    history: Vec<Position>,
+    error: Option<HamlError>
 }
 
 impl InputReader {
@@ -34,28 +49,105 @@ impl InputReader {
         InputReader {
             input: input,
             buffer: Vec::new(),
-            eof: false
+            eof: false,
+            started: false,
+            pos: Position::new(),
+            history: Vec::new(),
+            error: None
         }
     }
 
     pub fn get(&mut self) -> Option<char> {
-        if self.buffer.len() > 0 {
+        let c = if self.buffer.len() > 0 {
             self.buffer.shift()
         } else if self.eof {
             None
         } else {
-            match self.input.read_byte() {
-                Ok(b)   => Some(b as char),
-                Err(_)  => None
-            }
+            let c = match self.read_char() {
+                Some(BOM) if !self.started => {
+                    self.pos.offset += BOM.len_utf8_bytes();
+                    self.read_char()
+                },
+                c                          => c
+            };
+            self.started = true;
+            c
+        };
+        match c {
+            Some(c) => {
+                self.history.push(self.pos);
+                if self.history.len() > 2 * HISTORY_LEN {
+                    self.history = Vec::from_slice(self.history.slice_from(HISTORY_LEN));
+                }
+                self.pos = self.pos.advance(c);
+            },
+            None    => {}
         }
+        c
     }
 
     pub fn unget(&mut self, c: char) {
+        match self.history.pop() {
+            Some(pos) => self.pos = pos,
+            None      => {}
+        }
         self.buffer.unshift(c)
     }
 
+    // position of the next char
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    // an empty span at the position of the next char
+    pub fn here(&self) -> Span {
+        Span::from_positions(self.pos, self.pos)
+    }
+
     pub fn unget_eof(&mut self) {
         self.eof = true
     }
-}
\ No newline at end of file
+
+    // set when the input is not valid utf-8 or can't be read, the input is then
+    // considered ended
+    pub fn get_error(&self) -> Option<HamlError> {
+        self.error.clone()
+    }
+
+    // None at the end of the input, a read error is kept
+    fn read_byte(&mut self) -> Option<u8> {
+        match self.input.read_byte() {
+            Ok(b)                            => Some(b),
+            Err(ref e) if e.kind == EndOfFile => None,
+            Err(e)                           => {
+                self.error = Some(error::read_error(self.here(), e.desc.to_owned()));
+                self.eof = true;
+                None
+            }
+        }
+    }
+
+    // decode the next utf-8 sequence of the input
+    fn read_char(&mut self) -> Option<char> {
+        let first = match self.read_byte() {
+            Some(b) => b,
+            None    => return None
+        };
+        let width = str::utf8_char_width(first);
+        if width == 1 { return Some(first as char) }
+        let mut bytes = vec!(first);
+        for _ in range(1, width) {
+            match self.read_byte() {
+                Some(b) => bytes.push(b),
+                None    => break
+            }
+        }
+        let decoded = if width == 0 || bytes.len() != width { None }
+                      else { str::from_utf8(bytes.as_slice()).map(|s| s.char_at(0)) };
+        if decoded.is_none() && self.error.is_none() {
+            self.error = Some(error::invalid_utf8(self.here()));
+            self.eof = true;
+        }
+        decoded
+    }
+}
@@ -0,0 +1,189 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use escape::escape_html;
+use expr;
+use expr::{Expr, Helpers, Lookup};
+
+#[deriving(Clone, Show, Eq)]
+pub enum TextPart {
+    Literal(~str),
+    // the source of a `#{...}` interpolation
+    Interpolation(~str),
+    // an expression already parsed
+    Script(Expr)
+}
+
+// split a text on the `#{...}` interpolations, `\#{` is kept as a literal `#{`.
+// An interpolation which is not closed is kept as literal text.
+pub fn parse(text: &str) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut literal = StrBuf::new();
+    let mut i = 0;
+    while i < text.len() {
+        let rest = text.slice_from(i);
+        if rest.starts_with("\\#{") {
+            literal.push_str("#{");
+            i += 3;
+        } else if rest.starts_with("#{") {
+            match find_closing_brace(rest.slice_from(2)) {
+                Some(end) => {
+                    if literal.len() > 0 {
+                        parts.push(Literal(literal.into_owned()));
+                        literal = StrBuf::new();
+                    }
+                    parts.push(Interpolation(rest.slice(2, 2 + end).trim().to_owned()));
+                    i += 3 + end;
+                },
+                None      => {
+                    literal.push_str(rest);
+                    break
+                }
+            }
+        } else {
+            let range = text.char_range_at(i);
+            literal.push_char(range.ch);
+            i = range.next;
+        }
+    }
+    if literal.len() > 0 { parts.push(Literal(literal.into_owned())); }
+    parts
+}
+
+fn find_closing_brace(s: &str) -> Option<uint> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{'               => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}'               => depth -= 1,
+            _                 => {}
+        }
+    }
+    None
+}
+
+// parse the expressions of the interpolations
+pub fn compile(parts: &Vec<TextPart>) -> Result<Vec<TextPart>, ~str> {
+    let mut compiled = Vec::new();
+    for part in parts.iter() {
+        compiled.push(match *part {
+            Interpolation(ref source) => Script(try!(expr::parse(source.as_slice()))),
+            ref p                     => p.clone()
+        });
+    }
+    Ok(compiled)
+}
+
+// the values of the interpolations are html escaped if `escape` is true,
+// except the safe values
+pub fn eval(parts: &Vec<TextPart>, vars: &Lookup, helpers: &Helpers,
+            escape: bool) -> Result<~str, ~str> {
+    let mut res = StrBuf::new();
+    for part in parts.iter() {
+        let value = match *part {
+            Literal(ref s)            => { res.push_str(s.as_slice()); continue },
+            Interpolation(ref source) => {
+                try!(expr::eval(&try!(expr::parse(source.as_slice())), vars, helpers))
+            },
+            Script(ref e)             => try!(expr::eval(e, vars, helpers))
+        };
+        let output = value.to_output();
+        if escape && !value.is_safe() { res.push_str(escape_html(output.as_slice())) }
+        else { res.push_str(output.as_slice()) }
+    }
+    Ok(res.into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use context::Context;
+    use expr::Helpers;
+    use interpolation::{parse, compile, eval, Literal, Interpolation};
+
+    #[test]
+    fn text_without_interpolation_is_literal() {
+        assert_eq!(vec!(Literal("Hello world".to_owned())), parse("Hello world"))
+    }
+
+    #[test]
+    fn text_with_interpolation_is_splitted() {
+        assert_eq!(vec!(Literal("Hello ".to_owned()), Interpolation("name".to_owned()),
+                        Literal("!".to_owned())),
+                   parse("Hello #{ name }!"))
+    }
+
+    #[test]
+    fn escaped_interpolation_is_literal() {
+        assert_eq!(vec!(Literal("Hello #{name}".to_owned())), parse("Hello \\#{name}"))
+    }
+
+    #[test]
+    fn unclosed_interpolation_is_literal() {
+        assert_eq!(vec!(Literal("Hello #{name".to_owned())), parse("Hello #{name"))
+    }
+
+    #[test]
+    fn interpolation_can_contain_braces() {
+        assert_eq!(vec!(Interpolation("{a}".to_owned())), parse("#{{a}}"))
+    }
+
+    #[test]
+    fn eval_interpolation_with_context() {
+        let mut ctx = Context::new();
+        ctx.set_value("name", "John".to_owned());
+        assert_eq!(Ok("Hello John!".to_owned()),
+                   eval(&parse("Hello #{name}!"), &ctx, &Helpers::new(), false))
+    }
+
+    #[test]
+    fn eval_escape_only_interpolated_values() {
+        let mut ctx = Context::new();
+        ctx.set_value("food", "cheese & crackers".to_owned());
+        assert_eq!(Ok("<b>cheese &amp; crackers</b>".to_owned()),
+                   eval(&parse("<b>#{food}</b>"), &ctx, &Helpers::new(), true))
+    }
+
+    #[test]
+    fn eval_typed_and_nested_values() {
+        let mut ctx = Context::new();
+        ctx.set("count", 3i);
+        ctx.set("names", vec!("a & b"));
+        assert_eq!(Ok("3 a &amp; b".to_owned()),
+                   eval(&parse("#{count} #{names.0}"), &ctx, &Helpers::new(), true))
+    }
+
+    #[test]
+    fn unknown_value_is_empty() {
+        assert_eq!(Ok("Hello !".to_owned()),
+                   eval(&parse("Hello #{name}!"), &Context::new(), &Helpers::new(), false))
+    }
+
+    #[test]
+    fn interpolation_is_an_expression() {
+        let mut ctx = Context::new();
+        ctx.set("count", 2i);
+        assert_eq!(Ok("4 items".to_owned()),
+                   eval(&parse("#{count * 2} items"), &ctx, &Helpers::new(), false))
+        assert!(compile(&parse("#{count +}")).is_err())
+    }
+}
@@ -21,39 +21,77 @@
 // SOFTWARE.
 
 use std::vec::Vec;
+use std::result;
 
 use input_reader::InputReader;
 use token::Token;
 use token;
+use error;
+use error::HamlError;
+use interpolation;
+use span::{Position, Span};
 
 pub struct Lexer {
     input: InputReader,
-    tokens: Vec<Token>
+    tokens: Vec<Token>,
+    // spans[i] is the position of tokens[i] in the source
+    spans: Vec<Span>,
+    // start of the next token
+    start: Position,
+    skipped_lines: u32
 }
 
 pub enum LexResult {
     Ok,
-    Err(~str),
+    Err(HamlError),
     End
 }
 
+// a multiline line ends with a pipe separated from the content by a whitespace
+fn is_multiline(line: &str) -> bool {
+    line.len() > 2 && (line.ends_with(" |") || line.ends_with("\t|"))
+}
+
+fn strip_multiline(line: &str) -> ~str {
+    line.slice_to(line.len() - 1).trim_right().to_owned()
+}
+
 impl Lexer {
     pub fn new(input: InputReader) -> Lexer {
         Lexer {
             input: input,
-            tokens: Vec::new()
+            tokens: Vec::new(),
+            spans: Vec::new(),
+            start: Position::new(),
+            skipped_lines: 0
         }
     }
 
-    pub fn execute(&mut self) -> Vec<Token> {
+    pub fn get_spans(&self) -> Vec<Span> {
+        self.spans.clone()
+    }
+
+    // the token covers the source from the end of the previous token
+    fn push(&mut self, token: Token) {
+        let end = self.input.position();
+        self.tokens.push(token);
+        self.spans.push(Span::from_positions(self.start, end));
+        self.start = end;
+    }
+
+    // the source read since the end of the last token
+    fn current_span(&self) -> Span {
+        Span::from_positions(self.start, self.input.position())
+    }
+
+    pub fn execute(&mut self) -> Result<Vec<Token>, HamlError> {
         loop {
             match self.lex_line() {
                 Ok          => {/* continue */},
-                Err(msg)    => { println!("error: {}", msg); break },
-                End         => break
+                Err(e)      => return result::Err(e),
+                End         => return result::Ok(self.tokens.clone())
            }
         }
-        self.tokens.clone()
     }
 
     fn next_is(&mut self, c: char) -> bool {
@@ -89,7 +127,7 @@ impl Lexer {
             }
         }
         if len > 0 {
-            self.tokens.push(token::INDENT(c, len));
+            self.push(token::INDENT(c, len));
             true
         } else { false }
     }
@@ -99,47 +137,155 @@ impl Lexer {
             self.get_all('\t') {}
     }
 
-    fn handle_plain_text(&mut self) {
-        fn clean_plain_text_after(c: Option<u8>, mut content: StrBuf) -> StrBuf {
-            match c {
-                Some(c) => {
-                    if c as char == ' ' || c as char == '\t' {
-                        clean_plain_text_after(unsafe {content.pop_byte() }, content)
-                    } else { content.push_char(c as char); content }
-                },
-                _                      => content
+    // read the end of the line without the surrounding whitespace
+    fn read_plain_text(&mut self) -> ~str {
+        let mut content = StrBuf::new();
+        loop {
+            match self.input.get() {
+                Some('\n')  => { self.input.unget('\n'); break },
+                Some(c)     => content.push_char(c),
+                None        => { self.input.unget_eof(); break }
             }
         }
 
-        fn clean_plain_text_before(c: Option<u8>, mut content: StrBuf) -> StrBuf {
-            match c {
-                Some(c) => {
-                    if c as char == ' ' || c as char == '\t' {
-                        clean_plain_text_before(unsafe { content.shift_byte() }, content)
-                    } else {
-                        let mut tmp = StrBuf::from_char(1, c as char);
-                        tmp.push_str(content.into_owned()); tmp }
-                },
-                _                      => content
+        // remove whitespace around the text
+        content = StrBuf::from_str(content.as_slice().trim_chars(&[' ', '\t']));
+
+        // join the following lines ending with a pipe
+        if is_multiline(content.as_slice()) {
+            content = StrBuf::from_str(strip_multiline(content.as_slice()));
+            loop {
+                match self.handle_multiline() {
+                    Some(line) => { content.push_char(' '); content.push_str(line); },
+                    None       => break
+                }
             }
         }
+        content.into_owned()
+    }
 
+    fn handle_plain_text(&mut self) {
+        self.skip_whitespace();
+        let content = self.read_plain_text();
+        if content.len() > 0 {
+            let mut parts = interpolation::parse(content.as_slice());
+            let token = if parts.len() == 1 {
+                match parts.pop().unwrap() {
+                    interpolation::Literal(text) => token::PLAIN_TEXT(text),
+                    part                         => token::INTERPOLATED(vec!(part))
+                }
+            } else {
+                token::INTERPOLATED(parts)
+            };
+            self.push(token);
+        }
+    }
 
-        let mut content = StrBuf::new();
+    // read the next line if it is a continuation of a multiline block,
+    // otherwise give it back to the input.
+    fn handle_multiline(&mut self) -> Option<~str> {
+        if !self.next_is('\n') { return None }
+        let mut line = StrBuf::new();
         loop {
             match self.input.get() {
-                Some('\n')  => { self.input.unget('\n'); break },
-                Some(c)     => content.push_char(c),
-                None        => { self.input.unget_eof(); break }
+                Some('\n') => { self.input.unget('\n'); break },
+                Some(c)    => line.push_char(c),
+                None       => { self.input.unget_eof(); break }
             }
         }
+        if is_multiline(line.as_slice().trim()) {
+            self.skipped_lines += 1;
+            Some(strip_multiline(line.as_slice().trim()))
+        } else {
+            for c in line.as_slice().chars().rev() { self.input.unget(c); }
+            self.input.unget('\n');
+            None
+        }
+    }
 
-        // remove whitespace before the text
-        content = clean_plain_text_before(unsafe { content.shift_byte() }, content);
-        // remove whitespace after the text
-        content = clean_plain_text_after(unsafe { content.pop_byte() }, content);
+    // indentation of the current line
+    fn current_indent(&self) -> u32 {
+        let mut indent = 0;
+        for t in self.tokens.iter().rev() {
+            match *t {
+                token::INDENT(_, l) => indent += l,
+                _                   => break
+            }
+        }
+        indent
+    }
 
-        if content.len() > 0 { self.tokens.push(token::PLAIN_TEXT(content.into_owned()));  }
+    // read the next line if it is more indented than `indent` or blank,
+    // otherwise give it back to the input.
+    fn handle_filter_line(&mut self, indent: u32) -> Option<~str> {
+        if !self.next_is('\n') { return None }
+        let mut line = StrBuf::new();
+        loop {
+            match self.input.get() {
+                Some('\n') => { self.input.unget('\n'); break },
+                Some(c)    => line.push_char(c),
+                None       => { self.input.unget_eof(); break }
+            }
+        }
+        let line_indent = line.as_slice().chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        if line.as_slice().trim().len() == 0 || line_indent as u32 > indent {
+            self.skipped_lines += 1;
+            Some(line.into_owned())
+        } else {
+            for c in line.as_slice().chars().rev() { self.input.unget(c); }
+            self.input.unget('\n');
+            None
+        }
+    }
+
+    // a filter `:name` capture the raw content of the nested lines,
+    // End when the line is not a filter
+    fn handle_filter(&mut self) -> LexResult {
+        if !self.next_is(':') { return End }
+        let name = self.handle_identifier();
+        if name.len() == 0 {
+            self.input.unget(':');
+            return End
+        }
+        // only whitespaces can follow the name, the content is on the nested lines
+        while self.next_is(' ') || self.next_is('\t') {}
+        let text_start = self.input.position();
+        let mut text = false;
+        loop {
+            match self.input.get() {
+                Some('\n') => { self.input.unget('\n'); break },
+                Some(_)    => text = true,
+                None       => { self.input.unget_eof(); break }
+            }
+        }
+        if text {
+            let span = Span::from_positions(text_start, self.input.position());
+            return Err(error::text_after_filter(span, name))
+        }
+
+        let indent = self.current_indent();
+        let mut lines: Vec<~str> = Vec::new();
+        loop {
+            match self.handle_filter_line(indent) {
+                Some(line) => lines.push(line),
+                None       => break
+            }
+        }
+        // trailing blank lines are not part of the filter
+        while lines.len() > 0 && lines.last().unwrap().trim().len() == 0 {
+            lines.pop();
+        }
+        // the indentation of the first line is removed from each line
+        let base = match lines.iter().next() {
+            Some(l) => l.chars().take_while(|c| *c == ' ' || *c == '\t').count(),
+            None    => 0
+        };
+        let content: Vec<~str> = lines.iter().map(|l| {
+            let strip = l.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+            l.slice_from(if strip < base { strip } else { base }).to_owned()
+        }).collect();
+        self.push(token::FILTER(name, content.as_slice().connect("\n")));
+        Ok
     }
 
     fn handle_comments(&mut self) -> bool {
@@ -147,14 +293,16 @@ impl Lexer {
             Some('-')    => {
                 match self.input.get() {
                     Some('#')    => {
-                        self.tokens.push(token::HAML_COMMENT);
+                        self.push(token::HAML_COMMENT);
                         self.handle_plain_text();
                         true
                     }
                     Some(next_c) => {
+                        // the rest of the line is code
                         self.input.unget(next_c);
-                        self.input.unget('-');
-                        false
+                        self.push(token::SILENT_SCRIPT);
+                        self.handle_script();
+                        true
                     },
                     None         => {
                         self.input.unget_eof();
@@ -164,7 +312,18 @@ impl Lexer {
                 }
             }
             Some('/')    => {
-                self.tokens.push(token::HTML_COMMENT);
+                if self.next_is('[') {
+                    self.handle_conditional_comment(false);
+                } else if self.next_is('!') {
+                    if self.next_is('[') {
+                        self.handle_conditional_comment(true);
+                    } else {
+                        self.input.unget('!');
+                        self.push(token::HTML_COMMENT);
+                    }
+                } else {
+                    self.push(token::HTML_COMMENT);
+                }
                 self.handle_plain_text();
                 true
             }
@@ -173,10 +332,49 @@ impl Lexer {
         }
     }
 
+    // read the condition of `/[if IE]`, `/![if !IE]` for a downlevel-revealed comment.
+    // A condition which is not closed is kept as plain text.
+    fn handle_conditional_comment(&mut self, revealed: bool) {
+        let mut condition = StrBuf::new();
+        let mut depth = 0;
+        loop {
+            match self.input.get() {
+                Some(']') if depth == 0 => break,
+                Some('\n')              => {
+                    self.input.unget('\n');
+                    for c in condition.as_slice().chars().rev() { self.input.unget(c); }
+                    self.input.unget('[');
+                    if revealed { self.input.unget('!'); }
+                    self.push(token::HTML_COMMENT);
+                    return
+                },
+                Some(c)                 => {
+                    if c == '[' { depth += 1; }
+                    if c == ']' { depth -= 1; }
+                    condition.push_char(c);
+                },
+                None                    => {
+                    self.input.unget_eof();
+                    for c in condition.as_slice().chars().rev() { self.input.unget(c); }
+                    self.input.unget('[');
+                    if revealed { self.input.unget('!'); }
+                    self.push(token::HTML_COMMENT);
+                    return
+                }
+            }
+        }
+        let condition = condition.as_slice().trim().to_owned();
+        if revealed {
+            self.push(token::REVEALED_COMMENT(condition));
+        } else {
+            self.push(token::CONDITIONAL_COMMENT(condition));
+        }
+    }
+
     fn handle_doctype(&mut self) {
         if self.next_is('!') {
             if self.next_is('!') {
-                self.tokens.push(token::DOCTYPE)
+                self.push(token::DOCTYPE)
             } else {
                 self.input.unget('!');
                 self.input.unget('!');
@@ -204,11 +402,13 @@ impl Lexer {
         name.into_owned()
     }
 
-    fn handle_tag(&mut self) {
+    fn handle_tag(&mut self) -> bool {
+        let mut found = false;
         // check first if there is a '%' tag
         if self.next_is('%') {
             let identifier = self.handle_identifier();
-            self.tokens.push(token::TAG(identifier));
+            self.push(token::TAG(identifier));
+            found = true;
         }
 
         // then check for additionnal '.' class or '#' id
@@ -217,20 +417,311 @@ impl Lexer {
                 Some('!') => { self.handle_doctype(); break },
                 Some('#') => {
                     let identifier = self.handle_identifier();
-                    self.tokens.push(token::ID(identifier));
+                    self.push(token::ID(identifier));
+                    found = true;
                 },
                 Some('.') => {
                     let identifier = self.handle_identifier();
-                    self.tokens.push(token::CLASS(identifier));
+                    self.push(token::CLASS(identifier));
+                    found = true;
                 },
                 Some(c_next) => { self.input.unget(c_next); break },
                 None         => { self.input.unget_eof(); break }
             }
         }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.input.get() {
+                Some(' ') | Some('\t') => {},
+                Some(c)               => { self.input.unget(c); break },
+                None                  => { self.input.unget_eof(); break }
+            }
+        }
+        self.start = self.input.position();
     }
 
-    fn handle_attribute(&mut self) {
-        unimplemented!()
+    // read a quoted string, the opening quote is already consumed.
+    // return None if the string is not terminated on the current line.
+    fn handle_string(&mut self, quote: char) -> Option<~str> {
+        let mut content = StrBuf::new();
+        loop {
+            match self.input.get() {
+                Some('\\') => {
+                    match self.input.get() {
+                        Some('n')  => content.push_char('\n'),
+                        Some('t')  => content.push_char('\t'),
+                        Some('\n') => { self.input.unget('\n'); return None },
+                        Some(c)    => {
+                            if c != quote && c != '\\' { content.push_char('\\'); }
+                            content.push_char(c)
+                        },
+                        None       => { self.input.unget_eof(); return None }
+                    }
+                },
+                Some('\n') => { self.input.unget('\n'); return None },
+                Some(c)    => {
+                    if c == quote { return Some(content.into_owned()) }
+                    content.push_char(c)
+                },
+                None       => { self.input.unget_eof(); return None }
+            }
+        }
+    }
+
+    // read an attribute name, either `:name =>`, `"name" =>` or `name:`
+    fn handle_attribute_name(&mut self) -> LexResult {
+        let name = match self.input.get() {
+            Some(':') => {
+                if self.next_is('"') {
+                    self.handle_string('"')
+                } else if self.next_is('\'') {
+                    self.handle_string('\'')
+                } else {
+                    Some(self.handle_identifier())
+                }
+            },
+            Some(q) if q == '"' || q == '\'' => self.handle_string(q),
+            Some(c) => {
+                // new style `name: value`
+                self.input.unget(c);
+                let name = self.handle_identifier();
+                if name.len() == 0 || !self.next_is(':') {
+                    return Err(error::invalid_attribute_name(self.current_span()))
+                }
+                self.push(token::ATTRIBUTE(name));
+                return Ok
+            },
+            None    => {
+                self.input.unget_eof();
+                return Err(error::unterminated_attributes(self.current_span()))
+            }
+        };
+        match name {
+            Some(n) => {
+                if n.len() == 0 { return Err(error::invalid_attribute_name(self.current_span())) }
+                self.skip_whitespace();
+                if !(self.next_is('=') && self.next_is('>')) {
+                    return Err(error::invalid_attribute_name(self.current_span()))
+                }
+                self.push(token::ATTRIBUTE(n));
+                self.push(token::RARROW);
+                Ok
+            },
+            None    => Err(error::unterminated_string(self.current_span()))
+        }
+    }
+
+    fn last_attribute_name(&self) -> ~str {
+        for t in self.tokens.iter().rev() {
+            match *t {
+                token::ATTRIBUTE(ref name) => return name.clone(),
+                _                          => {}
+            }
+        }
+        "".to_owned()
+    }
+
+    fn remove_last_attribute(&mut self) {
+        loop {
+            self.spans.pop();
+            match self.tokens.pop() {
+                Some(token::ATTRIBUTE(_)) | None => break,
+                _                                => {}
+            }
+        }
+    }
+
+    // read an attribute value: a quoted string, a symbol, a number or a boolean
+    fn handle_attribute_value(&mut self) -> LexResult {
+        let value = match self.input.get() {
+            Some(q) if q == '"' || q == '\'' => {
+                match self.handle_string(q) {
+                    Some(v) => v,
+                    None    => return Err(error::unterminated_string(self.current_span()))
+                }
+            },
+            Some(':') => self.handle_identifier(),
+            Some(c)   => {
+                self.input.unget(c);
+                let value = self.handle_identifier();
+                match value.as_slice() {
+                    // boolean attributes take their name as value
+                    "true"          => self.last_attribute_name(),
+                    // false and nil remove the attribute
+                    "false" | "nil" => { self.remove_last_attribute(); return Ok },
+                    v if v.len() > 0 && v.chars().all(|c| c.is_digit()) => value.clone(),
+                    _               => {
+                        return Err(error::invalid_attribute_value(self.current_span()))
+                    }
+                }
+            },
+            None      => {
+                self.input.unget_eof();
+                return Err(error::unterminated_attributes(self.current_span()))
+            }
+        };
+        self.push(token::STR(value));
+        Ok
+    }
+
+    fn at_end_of_line(&mut self) -> bool {
+        match self.input.get() {
+            Some('\n') => { self.input.unget('\n'); true },
+            Some(c)    => { self.input.unget(c); false },
+            None       => { self.input.unget_eof(); true }
+        }
+    }
+
+    fn handle_attribute_hash(&mut self) -> LexResult {
+        self.push(token::LBRACE);
+        loop {
+            self.skip_whitespace();
+            if self.next_is('}') { break }
+            match self.handle_attribute_name() {
+                Ok  => {},
+                res => return res
+            }
+            self.skip_whitespace();
+            match self.handle_attribute_value() {
+                Ok  => {},
+                res => return res
+            }
+            self.skip_whitespace();
+            if self.next_is('}') { break }
+            if !self.next_is(',') {
+                return Err(error::unterminated_attributes(self.current_span()))
+            }
+        }
+        self.push(token::RBRACE);
+        Ok
+    }
+
+    fn handle_html_attribute_name(&mut self) -> ~str {
+        let mut name = StrBuf::new();
+        loop {
+            match self.input.get() {
+                Some(c) => {
+                    if c.is_alphanumeric() || c == '-' || c == '_' || c == ':' {
+                        name.push_char(c);
+                    } else {
+                        self.input.unget(c);
+                        break
+                    }
+                }
+                None    =>  { self.input.unget_eof(); break }
+            }
+        }
+        name.into_owned()
+    }
+
+    fn handle_unquoted_value(&mut self) -> ~str {
+        let mut value = StrBuf::new();
+        loop {
+            match self.input.get() {
+                Some(c) => {
+                    if c == ' ' || c == '\t' || c == '\n' || c == ')' {
+                        self.input.unget(c);
+                        break
+                    }
+                    value.push_char(c);
+                }
+                None    =>  { self.input.unget_eof(); break }
+            }
+        }
+        value.into_owned()
+    }
+
+    // html style attributes: `(name="value" other=value boolean)`
+    fn handle_html_attributes(&mut self) -> LexResult {
+        self.push(token::LPARENT);
+        loop {
+            self.skip_whitespace();
+            if self.next_is(')') { break }
+            if self.at_end_of_line() {
+                return Err(error::unterminated_attributes(self.current_span()))
+            }
+            let name = self.handle_html_attribute_name();
+            if name.len() == 0 { return Err(error::invalid_attribute_name(self.current_span())) }
+            self.skip_whitespace();
+            let value = if self.next_is('=') {
+                self.skip_whitespace();
+                if self.next_is('"') {
+                    self.handle_string('"')
+                } else if self.next_is('\'') {
+                    self.handle_string('\'')
+                } else {
+                    let v = self.handle_unquoted_value();
+                    if v.len() == 0 {
+                        return Err(error::invalid_attribute_value(self.current_span()))
+                    }
+                    Some(v)
+                }
+            } else {
+                // boolean attributes take their name as value
+                Some(name.clone())
+            };
+            match value {
+                Some(v) => {
+                    self.push(token::ATTRIBUTE(name));
+                    self.push(token::STR(v));
+                },
+                None    => return Err(error::unterminated_string(self.current_span()))
+            }
+        }
+        self.push(token::RPARENT);
+        Ok
+    }
+
+    // object reference: `[object]` or `[object, :prefix]`
+    fn handle_object_ref(&mut self) -> LexResult {
+        self.push(token::LBRACKET);
+        self.skip_whitespace();
+        let name = self.handle_identifier();
+        if name.len() == 0 { return Err(error::invalid_object_ref(self.current_span())) }
+        self.push(token::VAR(name));
+        self.skip_whitespace();
+        if self.next_is(',') {
+            self.skip_whitespace();
+            let prefix = if self.next_is(':') {
+                Some(self.handle_identifier())
+            } else if self.next_is('"') {
+                self.handle_string('"')
+            } else if self.next_is('\'') {
+                self.handle_string('\'')
+            } else {
+                return Err(error::invalid_object_ref(self.current_span()))
+            };
+            match prefix {
+                Some(p) => self.push(token::STR(p)),
+                None    => return Err(error::unterminated_string(self.current_span()))
+            }
+            self.skip_whitespace();
+        }
+        if !self.next_is(']') { return Err(error::invalid_object_ref(self.current_span())) }
+        self.push(token::RBRACKET);
+        Ok
+    }
+
+    // hash, html style attributes and object reference can follow each other in any order
+    fn handle_attribute(&mut self) -> LexResult {
+        loop {
+            let res = if self.next_is('{') {
+                self.handle_attribute_hash()
+            } else if self.next_is('(') {
+                self.handle_html_attributes()
+            } else if self.next_is('[') {
+                self.handle_object_ref()
+            } else {
+                return Ok
+            };
+            match res {
+                Ok  => {},
+                res => return res
+            }
+        }
     }
 
     fn handle_escape_line(&mut self) {
@@ -239,9 +730,51 @@ impl Lexer {
         }
     }
 
-    fn handle_assign(&mut self) {
+    // the content of a script line is kept raw, it is an expression
+    fn handle_script(&mut self) {
+        let content = self.read_plain_text();
+        if content.len() > 0 { self.push(token::PLAIN_TEXT(content)); }
+    }
+
+    fn next_is_whitespace(&mut self) -> bool {
+        match self.input.get() {
+            Some(c) => { self.input.unget(c); c == ' ' || c == '\t' },
+            None    => { self.input.unget_eof(); false }
+        }
+    }
+
+    // return true if the rest of the line is a script
+    fn handle_assign(&mut self) -> bool {
         if self.next_is('=') {
-            self.tokens.push(token::ASSIGN)
+            self.push(token::ASSIGN);
+            true
+        } else if self.next_is('~') {
+            self.push(token::PRESERVE_ASSIGN);
+            true
+        } else if self.next_is('&') {
+            if self.next_is('=') {
+                self.push(token::ESCAPED_ASSIGN);
+                true
+            } else if self.next_is_whitespace() {
+                self.push(token::ESCAPED);
+                false
+            } else {
+                self.input.unget('&');
+                false
+            }
+        } else if self.next_is('!') {
+            if self.next_is('=') {
+                self.push(token::UNESCAPED_ASSIGN);
+                true
+            } else if self.next_is_whitespace() {
+                self.push(token::UNESCAPED);
+                false
+            } else {
+                self.input.unget('!');
+                false
+            }
+        } else {
+            false
         }
     }
 
@@ -259,6 +792,7 @@ impl Lexer {
                 &token::INDENT(_, _) => {
                     let pos = get_blankline_begin(&self.tokens, i - 1);
                     self.tokens.truncate(pos);
+                    self.spans.truncate(pos);
                 },
                 _                   => { /* do nothing  */ }
             }
@@ -266,45 +800,90 @@ impl Lexer {
 
     }
 
+    // `>` and `<` can follow the tag in any order
+    fn handle_whitespace_removal(&mut self) {
+        let mut outer = false;
+        let mut inner = false;
+        loop {
+            if !outer && self.next_is('>') {
+                self.push(token::REMOVE_WS_OUTER);
+                outer = true;
+            } else if !inner && self.next_is('<') {
+                self.push(token::REMOVE_WS_INNER);
+                inner = true;
+            } else {
+                break
+            }
+        }
+    }
+
     fn handle_empty_tag(&mut self) {
         if self.next_is('/') {
-            self.tokens.push(token::CLOSING_EMPTY);
+            self.push(token::CLOSING_EMPTY);
         }
     }
 
     fn lex_line(&mut self) -> LexResult {
         self.handle_indent();
         self.handle_escape_line();
-        // no comments found -> try to find a tag
+        // no comments or filter found -> try to find a tag
         if !self.handle_comments() {
-            self.handle_tag();
-            // self.handle_attribute();
-            self.handle_empty_tag();
-            self.handle_assign();
-            self.handle_plain_text();
+            match self.handle_filter() {
+                Ok       => {},
+                Err(msg) => return Err(msg),
+                End      => {
+                    if self.handle_tag() {
+                        match self.handle_attribute() {
+                            Err(msg) => return Err(msg),
+                            _        => {}
+                        }
+                        self.handle_whitespace_removal();
+                    }
+                    self.handle_empty_tag();
+                    if self.handle_assign() {
+                        self.handle_script();
+                    } else {
+                        self.handle_plain_text();
+                    }
+                }
+            }
         }
         match self.input.get() {
             Some(_) => {
                 self.check_blankline();
-                self.tokens.push(token::EOL);
+                self.push(token::EOL);
+                // keep one blank line for each line consumed by a multiline or
+                // a filter so the following lines keep their line number.
+                while self.skipped_lines > 0 {
+                    self.push(token::EOL);
+                    self.skipped_lines -= 1;
+                }
                 Ok
             },
-            None    => { self.tokens.push(token::EOF); End }
+            None    => {
+                match self.input.get_error() {
+                    Some(msg) => Err(msg),
+                    None      => { self.push(token::EOF); End }
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use lexer::Lexer;
+    use lexer::{Lexer, Ok, Err};
     use input_reader::InputReader;
+    use interpolation::{Literal, Interpolation};
     use token;
+    use span::Span;
+    use error::{HamlError, InvalidUtf8, ReadError, TextAfterFilter, UnterminatedAttributes};
 
     mod mock {
-        use std::io::{Reader, IoError, EndOfFile, IoResult};
+        use std::io::{Reader, IoError, EndOfFile, OtherIoError, IoResult};
 
         pub struct Input {
-            pub input: ~str
+            pub input: Vec<u8>
         }
 
         impl Reader for Input {
@@ -313,8 +892,8 @@ mod tests {
             }
 
             fn read_byte(&mut self) -> IoResult<u8> {
-                match self.input.shift_char() {
-                    Some(c) => Ok(c as u8),
+                match self.input.shift() {
+                    Some(b) => Ok(b),
                     None    => Err(IoError {
                         kind: EndOfFile,
                         desc: "",
@@ -323,13 +902,105 @@ mod tests {
                 }
             }
         }
+
+        // fail after reading the input
+        pub struct Failing {
+            pub input: Vec<u8>
+        }
+
+        impl Reader for Failing {
+            fn read(&mut self, _: &mut [u8]) -> IoResult<uint> {
+                Ok(0)
+            }
+
+            fn read_byte(&mut self) -> IoResult<u8> {
+                match self.input.shift() {
+                    Some(b) => Ok(b),
+                    None    => Err(IoError {
+                        kind: OtherIoError,
+                        desc: "device error",
+                        detail: None,
+                    })
+                }
+            }
+        }
     }
 
     fn prepare_test_lexer(haml_str: ~str) -> Lexer {
-        let input_reader = InputReader::new(~mock::Input { input: haml_str } as ~Reader);
+        prepare_test_lexer_from_bytes(haml_str.as_bytes().iter().map(|b| *b).collect())
+    }
+
+    fn prepare_test_lexer_from_bytes(input: Vec<u8>) -> Lexer {
+        let input_reader = InputReader::new(~mock::Input { input: input } as ~Reader);
         Lexer::new(input_reader)
     }
 
+    #[test]
+    fn read_error_is_not_the_end_of_the_input() {
+        let input = ~mock::Failing { input: Vec::from_slice("%p a\n%p".as_bytes()) } as ~Reader;
+        let mut lexer = Lexer::new(InputReader::new(input));
+        assert_eq!(Some(HamlError::new(ReadError(~"device error"), Span::new(2, 3, 7, 0))),
+                   lexer.execute().err())
+    }
+
+    #[test]
+    fn lex_utf8_plain_text() {
+        let haml_str = ~"%p Ça marche, 日本語 ✓\n";
+        let expected = vec!(token::TAG(~"p"), token::PLAIN_TEXT(~"Ça marche, 日本語 ✓"),
+                            token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_skip_byte_order_mark() {
+        let haml_str = ~"\uFEFF%p\n";
+        let expected = vec!(token::TAG(~"p"), token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_token_spans() {
+        let haml_str = ~"%p.foo.bar hi\n  #x\n";
+        let expected = vec!(Span::new(1, 1, 0, 2), Span::new(1, 3, 2, 4), Span::new(1, 7, 6, 4),
+                            Span::new(1, 12, 11, 2), Span::new(1, 14, 13, 1),
+                            Span::new(2, 1, 14, 2), Span::new(2, 3, 16, 2), Span::new(2, 5, 18, 1),
+                            Span::new(3, 1, 19, 0));
+        let mut lexer = prepare_test_lexer(haml_str);
+        lexer.execute().unwrap();
+
+        assert_eq!(expected, lexer.get_spans())
+    }
+
+    #[test]
+    fn lex_token_spans_count_chars_not_bytes() {
+        let haml_str = ~"%p été\n%p.x\n";
+        let mut lexer = prepare_test_lexer(haml_str);
+        lexer.execute().unwrap();
+
+        assert_eq!(&Span::new(2, 3, 14, 2), lexer.get_spans().get(4))
+    }
+
+    #[test]
+    fn lex_invalid_utf8_sequence() {
+        let mut input: Vec<u8> = "%p\n%p ".as_bytes().iter().map(|b| *b).collect();
+        input.push(0xC3);
+        input.push(0x28);
+        let mut lexer = prepare_test_lexer_from_bytes(input);
+
+        match lexer.lex_line() {
+            Ok => {},
+            _  => fail!("first line should be valid")
+        }
+        match lexer.lex_line() {
+            Err(e) => assert_eq!(HamlError::new(InvalidUtf8, Span::new(2, 4, 6, 0)), e),
+            _      => fail!("invalid utf-8 sequence not reported")
+        }
+    }
+
     #[test]
     fn lex_plain_text() {
         let haml_str = ~"this is a plain text string\n";
@@ -337,7 +1008,7 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -346,7 +1017,7 @@ mod tests {
         let expected = vec!(token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -355,7 +1026,7 @@ mod tests {
         let expected = vec!(token::INDENT(' ', 2), token::TAG(~"tag"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -366,7 +1037,7 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -376,7 +1047,7 @@ mod tests {
                             token::CLASS(~"class"), token::PLAIN_TEXT(~"text"), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -386,7 +1057,7 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -396,7 +1067,7 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -406,7 +1077,7 @@ mod tests {
                             token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -416,7 +1087,7 @@ mod tests {
                             token::CLASS(~"class"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -425,7 +1096,7 @@ mod tests {
         let expected = vec!(token::EOL, token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -434,7 +1105,7 @@ mod tests {
         let expected = vec!(token::EOL, token::TAG(~"t"), token::EOL, token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -444,7 +1115,7 @@ mod tests {
                             token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -454,7 +1125,7 @@ mod tests {
                             token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -463,7 +1134,7 @@ mod tests {
         let expected = vec!(token::TAG(~"t"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -472,7 +1143,7 @@ mod tests {
         let expected = vec!(token::DOCTYPE, token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -481,7 +1152,7 @@ mod tests {
         let expected = vec!(token::DOCTYPE, token::PLAIN_TEXT(~"Strict"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -490,7 +1161,7 @@ mod tests {
         let expected = vec!(token::PLAIN_TEXT(~"!!"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -499,7 +1170,7 @@ mod tests {
         let expected = vec!(token::PLAIN_TEXT(~"!"), token::EOL, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -508,7 +1179,7 @@ mod tests {
         let expected = vec!(token::HTML_COMMENT, token::PLAIN_TEXT(~"%t hello world"), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -518,7 +1189,16 @@ mod tests {
                             token::TAG(~"t"), token::PLAIN_TEXT(~"hello world"), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_silent_script() {
+        let haml_str = ~"- if a\n  %p";
+        let expected = vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT(~"if a"), token::EOL,
+                            token::INDENT(' ', 2), token::TAG(~"p"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -527,7 +1207,7 @@ mod tests {
         let expected = vec!(token::HAML_COMMENT, token::PLAIN_TEXT(~"%t hello world"), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -537,7 +1217,7 @@ mod tests {
                             token::TAG(~"t"), token::PLAIN_TEXT(~"hello world"), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -547,7 +1227,7 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -556,7 +1236,7 @@ mod tests {
         let expected = vec!(token::TAG(~"t"), token::ASSIGN, token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -565,7 +1245,7 @@ mod tests {
         let expected = vec!(token::TAG(~"t"), token::PLAIN_TEXT(~"="), token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 
     #[test]
@@ -575,6 +1255,336 @@ mod tests {
                             token::EOF);
         let mut lexer = prepare_test_lexer(haml_str);
 
-        assert_eq!(expected, lexer.execute())
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_old_style_attributes() {
+        let haml_str = ~"%a{:href => \"/x\", :title => 'y'}";
+        let expected = vec!(token::TAG(~"a"), token::LBRACE, token::ATTRIBUTE(~"href"),
+                            token::RARROW, token::STR(~"/x"), token::ATTRIBUTE(~"title"),
+                            token::RARROW, token::STR(~"y"), token::RBRACE, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_new_style_attributes() {
+        let haml_str = ~"%a{href: \"/x\", title: :y} link";
+        let expected = vec!(token::TAG(~"a"), token::LBRACE, token::ATTRIBUTE(~"href"),
+                            token::STR(~"/x"), token::ATTRIBUTE(~"title"), token::STR(~"y"),
+                            token::RBRACE, token::PLAIN_TEXT(~"link"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_attribute_string_with_escapes() {
+        let haml_str = ~"%a{title: \"say \\\"hi\\\"\"}";
+        let expected = vec!(token::TAG(~"a"), token::LBRACE, token::ATTRIBUTE(~"title"),
+                            token::STR(~"say \"hi\""), token::RBRACE, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_boolean_attributes() {
+        let haml_str = ~"%input{checked: true, disabled: false}";
+        let expected = vec!(token::TAG(~"input"), token::LBRACE, token::ATTRIBUTE(~"checked"),
+                            token::STR(~"checked"), token::RBRACE, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_unterminated_attributes_is_an_error() {
+        let mut lexer = prepare_test_lexer(~"%a{href: \"/x\"\n");
+
+        match lexer.lex_line() {
+            Err(_) => {},
+            _      => fail!("unterminated attribute list should be an error")
+        }
+    }
+
+    #[test]
+    fn execute_returns_lexing_errors() {
+        let mut lexer = prepare_test_lexer(~"%p\n%a{href: \"/x\"\n%p\n");
+
+        assert_eq!(Some(HamlError::new(UnterminatedAttributes, Span::new(2, 14, 16, 0))),
+                   lexer.execute().err())
+    }
+
+    #[test]
+    fn brace_without_tag_is_plain_text() {
+        let haml_str = ~"{not: attributes}";
+        let expected = vec!(token::PLAIN_TEXT(~"{not: attributes}"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_html_style_attributes() {
+        let haml_str = ~"%img(src=\"a.png\" alt='x' width=20 disabled)";
+        let expected = vec!(token::TAG(~"img"), token::LPARENT, token::ATTRIBUTE(~"src"),
+                            token::STR(~"a.png"), token::ATTRIBUTE(~"alt"), token::STR(~"x"),
+                            token::ATTRIBUTE(~"width"), token::STR(~"20"),
+                            token::ATTRIBUTE(~"disabled"), token::STR(~"disabled"),
+                            token::RPARENT, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_html_style_and_hash_attributes_on_the_same_tag() {
+        let haml_str = ~"%a(href='/x'){title: \"y\"} link";
+        let expected = vec!(token::TAG(~"a"), token::LPARENT, token::ATTRIBUTE(~"href"),
+                            token::STR(~"/x"), token::RPARENT, token::LBRACE,
+                            token::ATTRIBUTE(~"title"), token::STR(~"y"), token::RBRACE,
+                            token::PLAIN_TEXT(~"link"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_unterminated_html_attributes_is_an_error() {
+        let mut lexer = prepare_test_lexer(~"%img(src='a.png'\n");
+
+        match lexer.lex_line() {
+            Err(_) => {},
+            _      => fail!("unterminated attribute list should be an error")
+        }
+    }
+
+    #[test]
+    fn lex_object_reference() {
+        let haml_str = ~"%div[user]";
+        let expected = vec!(token::TAG(~"div"), token::LBRACKET, token::VAR(~"user"),
+                            token::RBRACKET, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_object_reference_with_prefix() {
+        let haml_str = ~"%div.c[user, :admin] text";
+        let expected = vec!(token::TAG(~"div"), token::CLASS(~"c"), token::LBRACKET,
+                            token::VAR(~"user"), token::STR(~"admin"), token::RBRACKET,
+                            token::PLAIN_TEXT(~"text"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_multiline_give_one_plain_text() {
+        let haml_str = ~"%p= a long |\n    script |\n  line |\n%p\n";
+        let expected = vec!(token::TAG(~"p"), token::ASSIGN,
+                            token::PLAIN_TEXT(~"a long script line"), token::EOL, token::EOL,
+                            token::EOL, token::TAG(~"p"), token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_multiline_stop_on_line_without_pipe() {
+        let haml_str = ~"a |\nb |\n  c\n";
+        let expected = vec!(token::PLAIN_TEXT(~"a b"), token::EOL, token::EOL,
+                            token::INDENT(' ', 2), token::PLAIN_TEXT(~"c"), token::EOL,
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn pipe_without_whitespace_is_not_multiline() {
+        let haml_str = ~"a|\nb |";
+        let expected = vec!(token::PLAIN_TEXT(~"a|"), token::EOL, token::PLAIN_TEXT(~"b"),
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_whitespace_removal_after_tag() {
+        let haml_str = ~"%a{href: '/'}<> link";
+        let expected = vec!(token::TAG(~"a"), token::LBRACE, token::ATTRIBUTE(~"href"),
+                            token::STR(~"/"), token::RBRACE, token::REMOVE_WS_INNER,
+                            token::REMOVE_WS_OUTER, token::PLAIN_TEXT(~"link"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_whitespace_removal_not_stuck_to_tag_is_plain_text() {
+        let haml_str = ~"%p <b>";
+        let expected = vec!(token::TAG(~"p"), token::PLAIN_TEXT(~"<b>"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_filter_capture_nested_lines() {
+        let haml_str = ~"%head\n  :javascript\n    var a = 1;\n\n      %p a\n  %p\n";
+        let expected = vec!(token::TAG(~"head"), token::EOL, token::INDENT(' ', 2),
+                            token::FILTER(~"javascript", ~"var a = 1;\n\n  %p a"), token::EOL,
+                            token::EOL, token::EOL, token::EOL, token::INDENT(' ', 2),
+                            token::TAG(~"p"), token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_filter_at_end_of_file() {
+        let haml_str = ~":plain\n  some text\n\n";
+        let expected = vec!(token::FILTER(~"plain", ~"some text"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_filter_with_text_on_its_line() {
+        let mut lexer = prepare_test_lexer(~":javascript alert(1)\n");
+        match lexer.lex_line() {
+            Err(e) => assert_eq!(HamlError::new(TextAfterFilter(~"javascript"),
+                                                Span::new(1, 13, 12, 8)), e),
+            _      => fail!("text after the filter not reported")
+        }
+
+        let mut lexer = prepare_test_lexer(~":plain  \n  text\n");
+        assert_eq!(vec!(token::FILTER(~"plain", ~"text"), token::EOF), lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn colon_without_name_is_plain_text() {
+        let haml_str = ~": text";
+        let expected = vec!(token::PLAIN_TEXT(~": text"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_interpolation_in_plain_text() {
+        let haml_str = ~"%p Hello #{user}!";
+        let expected = vec!(token::TAG(~"p"),
+                            token::INTERPOLATED(vec!(Literal(~"Hello "), Interpolation(~"user"),
+                                                     Literal(~"!"))),
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_escaped_interpolation_give_plain_text() {
+        let haml_str = ~"Hello \\#{user}";
+        let expected = vec!(token::PLAIN_TEXT(~"Hello #{user}"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_interpolation_in_attribute_is_kept_raw() {
+        let haml_str = ~"%a{href: \"/u/#{id}\"}";
+        let expected = vec!(token::TAG(~"a"), token::LBRACE, token::ATTRIBUTE(~"href"),
+                            token::STR(~"/u/#{id}"), token::RBRACE, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_escaping_assign() {
+        let haml_str = ~"%p&= a\n%p!= b\n";
+        let expected = vec!(token::TAG(~"p"), token::ESCAPED_ASSIGN, token::PLAIN_TEXT(~"a"),
+                            token::EOL, token::TAG(~"p"), token::UNESCAPED_ASSIGN,
+                            token::PLAIN_TEXT(~"b"), token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_escaping_plain_text() {
+        let haml_str = ~"& <a>\n! <b>\n";
+        let expected = vec!(token::ESCAPED, token::PLAIN_TEXT(~"<a>"), token::EOL,
+                            token::UNESCAPED, token::PLAIN_TEXT(~"<b>"), token::EOL, token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_script_is_not_interpolated() {
+        let haml_str = ~"= \"#{a}\"";
+        let expected = vec!(token::ASSIGN, token::PLAIN_TEXT(~"\"#{a}\""), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn ampersand_not_followed_by_whitespace_is_plain_text() {
+        let haml_str = ~"&amp;";
+        let expected = vec!(token::PLAIN_TEXT(~"&amp;"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_tilde_stuck_to_tag_give_preserve_assign() {
+        let haml_str = ~"%pre~ code";
+        let expected = vec!(token::TAG(~"pre"), token::PRESERVE_ASSIGN, token::PLAIN_TEXT(~"code"),
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_conditional_comment() {
+        let haml_str = ~"/[if lt IE 9]\n  %p old";
+        let expected = vec!(token::CONDITIONAL_COMMENT(~"if lt IE 9"), token::EOL,
+                            token::INDENT(' ', 2), token::TAG(~"p"), token::PLAIN_TEXT(~"old"),
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_revealed_conditional_comment() {
+        let haml_str = ~"/![if !IE] text";
+        let expected = vec!(token::REVEALED_COMMENT(~"if !IE"), token::PLAIN_TEXT(~"text"),
+                            token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
+    }
+
+    #[test]
+    fn lex_unclosed_conditional_comment_give_plain_text() {
+        let haml_str = ~"/[if IE";
+        let expected = vec!(token::HTML_COMMENT, token::PLAIN_TEXT(~"[if IE"), token::EOF);
+        let mut lexer = prepare_test_lexer(haml_str);
+
+        assert_eq!(expected, lexer.execute().unwrap())
     }
 }
@@ -39,13 +39,30 @@ extern crate collections;
 
 pub use format::{HtmlFormat, Xhtml, Html4, Html5};
 pub use engine::Engine;
+pub use context::{Context, HamlObjectRef};
+pub use span::Span;
+pub use error::HamlError;
+pub use value::{Value, ToValue};
+pub use expr::Helper;
+pub use loader::{TemplateLoader, MemoryLoader, FileSystemLoader};
 
 mod testing;
 mod format;
 mod engine;
+mod options;
+mod context;
+mod span;
 mod token;
 mod input_reader;
 mod lexer;
+mod attributes;
 mod dom_tree;
+mod render;
+mod loader;
 mod parser;
-mod error;
+pub mod error;
+mod escape;
+mod filter;
+mod interpolation;
+mod expr;
+pub mod value;
@@ -0,0 +1,104 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::str;
+use std::io::File;
+use collections::HashMap;
+
+// Find the source of the partials included with `= render "name"`.
+pub trait TemplateLoader {
+    // the source of the template, or why it can't be loaded
+    fn load(&self, name: &str) -> Result<~str, ~str>;
+}
+
+// templates registered by name
+pub struct MemoryLoader {
+    templates: HashMap<~str, ~str>
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader { templates: HashMap::new() }
+    }
+
+    pub fn add(&mut self, name: &str, source: &str) {
+        self.templates.insert(name.to_owned(), source.to_owned());
+    }
+}
+
+impl TemplateLoader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<~str, ~str> {
+        match self.templates.find(&name.to_owned()) {
+            Some(source) => Ok(source.clone()),
+            None         => Err("no such template".to_owned())
+        }
+    }
+}
+
+// "shared/card" is read from "root/shared/card.haml"
+pub struct FileSystemLoader {
+    root: Path
+}
+
+impl FileSystemLoader {
+    pub fn new(root: Path) -> FileSystemLoader {
+        FileSystemLoader { root: root }
+    }
+}
+
+impl TemplateLoader for FileSystemLoader {
+    fn load(&self, name: &str) -> Result<~str, ~str> {
+        // the templates can't be outside of the root
+        if name.starts_with("/") || name.split('/').any(|c| c == "..") {
+            return Err("the name must be relative to the templates directory".to_owned())
+        }
+        let path = self.root.join(format!("{}.haml", name));
+        let bytes = match File::open(&path).read_to_end() {
+            Ok(bytes) => bytes,
+            Err(e)    => return Err(format!("{}: {}", path.display(), e))
+        };
+        match str::from_utf8(bytes.as_slice()) {
+            Some(source) => Ok(source.to_owned()),
+            None         => Err(format!("{}: invalid utf-8", path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use loader::{TemplateLoader, MemoryLoader, FileSystemLoader};
+
+    #[test]
+    fn memory_loader_finds_added_templates() {
+        let mut loader = MemoryLoader::new();
+        loader.add("shared/card", "%p card");
+        assert_eq!(Ok("%p card".to_owned()), loader.load("shared/card"))
+        assert!(loader.load("card").is_err())
+    }
+
+    #[test]
+    fn file_system_loader_stays_in_its_root() {
+        let loader = FileSystemLoader::new(Path::new("templates"));
+        assert!(loader.load("../secret").is_err())
+        assert!(loader.load("/etc/passwd").is_err())
+    }
+}
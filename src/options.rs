@@ -0,0 +1,64 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use format::HtmlFormat;
+use expr::Helpers;
+
+// whitespace sensitive tags, their content is never reindented
+static default_preserve: [&'static str, ..2] = ["pre", "textarea"];
+// void tags, they are always self-closing
+static default_void: [&'static str, ..10] = ["meta", "img", "link", "br", "hr", "input", "area",
+                                             "param", "col", "base"];
+
+#[deriving(Clone)]
+pub struct Options {
+    pub html_fmt: HtmlFormat,
+    pub preserve: Vec<~str>,
+    pub void: Vec<~str>,
+    // render the attributes by name instead of the source order
+    pub sort_attributes: bool,
+    // escape the values written in the text when neither `&` nor `!` is used
+    pub escape_html: bool,
+    // the functions which can be called by the expressions
+    pub helpers: Helpers
+}
+
+impl Options {
+    pub fn new(html_fmt: HtmlFormat) -> Options {
+        Options {
+            html_fmt: html_fmt,
+            preserve: default_preserve.iter().map(|s| s.to_owned()).collect(),
+            void: default_void.iter().map(|s| s.to_owned()).collect(),
+            sort_attributes: false,
+            escape_html: false,
+            helpers: Helpers::new()
+        }
+    }
+
+    pub fn is_preserved(&self, tag: &str) -> bool {
+        self.preserve.iter().any(|t| t.as_slice() == tag)
+    }
+
+    pub fn is_void(&self, tag: &str) -> bool {
+        self.void.iter().any(|t| t.as_slice() == tag)
+    }
+}
@@ -21,16 +21,23 @@
 // SOFTWARE.
 
 use std::vec::Vec;
-use collections::HashMap;
+use std::mem;
 
-use dom_tree::{DomTree, Item};
+use attributes::Attributes;
+use dom_tree;
+use dom_tree::{DomTree, Item, EscapeMode, DefaultEscaping, Escaped, Unescaped};
 use format::HtmlFormat;
+use options::Options;
+use span::Span;
 use token::Token;
 use token;
 use error;
-
-static default_empty: [&'static str, ..10] = ["meta", "img", "link", "br", "hr", "input", "area",
-                                              "param", "col", "base"];
+use error::HamlError;
+use filter;
+use interpolation;
+use interpolation::{TextPart, Literal, Interpolation};
+use expr;
+use expr::Expr;
 
 #[deriving(Eq, Clone, Show)]
 pub enum TagType {
@@ -39,107 +46,180 @@ pub enum TagType {
     Unknown,
     HamlComment,
     HtmlComment,
+    ConditionalComment,
+    RevealedComment,
+    Filter,
+    // `- if`, `- unless`, `- elsif`, `- else` or `- for`
+    SilentScript(dom_tree::TagType),
+    // `= render "name", key: value`
+    Partial
 }
 
 pub struct Parser {
-    html_fmt: HtmlFormat,
+    opts: Options,
     tokens: Vec<Token>,
+    // spans[i] is the position of tokens[i] in the source
+    spans: Vec<Span>,
+    last_span: Span,
     dom_tree: DomTree,
-    c_line: u32,
     indent_length: u32,
     indent_char: char,
-    c_indent_lvl: u32
+    c_indent_lvl: u32,
+    content_started: bool
 }
 
 pub struct DCollector {
-    attributes: HashMap<~str, Vec<~str>>,
+    attributes: Attributes,
     tag: ~str,
-    content: ~str,
-    tag_type: TagType
+    content: Vec<TextPart>,
+    tag_type: TagType,
+    remove_outer_ws: bool,
+    remove_inner_ws: bool,
+    script: bool,
+    preserve: bool,
+    escape: EscapeMode,
+    condition: ~str,
+    self_closing: bool,
+    object_ref: Option<(~str, Option<~str>)>,
+    expr: Option<Expr>,
+    vars: Vec<~str>,
+    locals: Vec<(~str, Expr)>,
+    // `%tag= render "name"`, the partial nested under the tag
+    partial: Option<Item>,
+    span: Span
 }
 
-fn is_default_empty(tag: &str) -> bool {
-    default_empty.iter().fold(false, |b, &s| {
-        if s == tag {
-            true
-        } else {
-            b
-        }
-    })
+// spans of tokens which don't come from a source, only the line is known
+fn line_spans(tokens: &Vec<Token>) -> Vec<Span> {
+    let mut line = 1;
+    tokens.iter().map(|t| {
+        let span = Span::line(line);
+        if *t == token::EOL { line += 1; }
+        span
+    }).collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        },
+        _                                        => false
+    }
+}
+
+fn parse_expression(span: Span, source: &str) -> Result<Expr, HamlError> {
+    match expr::parse(source) {
+        Ok(e)    => Ok(e),
+        Err(msg) => Err(error::invalid_expression(span, msg))
+    }
 }
 
 impl DCollector {
     pub fn new() -> DCollector {
         DCollector {
-            attributes: HashMap::new(),
+            attributes: Attributes::new(),
             tag: "".to_owned(),
-            content: "".to_owned(),
-            tag_type: Unknown
+            content: Vec::new(),
+            tag_type: Unknown,
+            remove_outer_ws: false,
+            remove_inner_ws: false,
+            script: false,
+            preserve: false,
+            escape: DefaultEscaping,
+            condition: "".to_owned(),
+            self_closing: false,
+            object_ref: None,
+            expr: None,
+            vars: Vec::new(),
+            locals: Vec::new(),
+            partial: None,
+            span: Span::line(1)
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.tag == "".to_owned() && self.attributes.is_empty() && self.content == "".to_owned()
+        self.tag == "".to_owned() && self.attributes.is_empty() && self.content.is_empty()
     }
 
     pub fn is_inline(&self) -> bool {
-        (self.tag != "".to_owned() || !self.attributes.is_empty()) && self.content != "".to_owned()
+        (self.tag != "".to_owned() || !self.attributes.is_empty()) && !self.content.is_empty()
     }
 
     pub fn is_block(&self) -> bool {
-        (self.tag != "".to_owned() || !self.attributes.is_empty()) && self.content == "".to_owned()
+        (self.tag != "".to_owned() || !self.attributes.is_empty()) && self.content.is_empty()
     }
 
     pub fn is_plaintext(&self) -> bool {
-        self.tag == "".to_owned() && self.attributes.is_empty() && self.content != "".to_owned()
+        self.tag == "".to_owned() && self.attributes.is_empty() && !self.content.is_empty()
     }
 }
 
 impl Parser {
     pub fn new(html_fmt: HtmlFormat) -> Parser {
+        Parser::with_options(Options::new(html_fmt))
+    }
+
+    pub fn with_options(opts: Options) -> Parser {
         Parser {
-            html_fmt: html_fmt,
+            opts: opts,
             tokens: Vec::new(),
+            spans: Vec::new(),
+            last_span: Span::line(1),
             dom_tree: DomTree::new(),
-            c_line: 1,
             indent_length: 0,
             indent_char: 0u8 as char,
-            c_indent_lvl: 0
+            c_indent_lvl: 0,
+            content_started: false
         }
     }
 
-    fn check_indent_on_first_line(&mut self,) -> Result<(), ~str> {
+    // consume the current token
+    fn next(&mut self) -> Option<Token> {
+        match self.spans.shift() {
+            Some(span) => self.last_span = span,
+            None       => {}
+        }
+        self.tokens.shift()
+    }
+
+    // span of the current token
+    fn span(&self) -> Span {
+        match self.spans.as_slice().head() {
+            Some(span) => *span,
+            None       => self.last_span
+        }
+    }
+
+    fn check_indent_on_first_line(&mut self,) -> Result<(), HamlError> {
         loop {
             match self.tokens.get(0) {
-                &token::EOL          => {
-                    self.c_line += 1;
-                    self.tokens.shift();
-                },
-                &token::INDENT(_, _) => return Err(error::illegal_indent_at_begin(self.c_line)),
+                &token::EOL          => { self.next(); },
+                &token::INDENT(_, _) => return Err(error::illegal_indent_at_begin(self.span())),
                 _                    => return Ok(())
             }
         }
     }
 
-    fn mix_space_tab_indent(&mut self) -> Result<(), ~str> {
+    fn mix_space_tab_indent(&mut self) -> Result<(), HamlError> {
         match self.tokens.get(1) {
-            &token::INDENT(_, _) => Err(error::indent_using_line_and_space(self.c_line)),
+            &token::INDENT(_, _) => Err(error::indent_using_line_and_space(*self.spans.get(1))),
             _                    => Ok(())
         }
     }
 
-    fn inconsistent_indent(&mut self, c: char, length: u32) -> Result<(), ~str> {
+    fn inconsistent_indent(&mut self, c: char, length: u32) -> Result<(), HamlError> {
         if self.indent_char != c {
-            Err(error::inconsistent_indent(self.c_line, c, self.indent_char,
+            Err(error::inconsistent_indent(self.span(), c, self.indent_char,
                                            length, self.indent_length))
         } else if (length % self.indent_length) != 0 {
-            Err(error::inconsistent_indent(self.c_line, c, c, length, self.indent_length))
+            Err(error::inconsistent_indent(self.span(), c, c, length, self.indent_length))
         } else {
             Ok(())
         }
     }
 
-    fn indent_level(&mut self, length: u32) -> Result<(), ~str> {
+    fn indent_level(&mut self, length: u32) -> Result<(), HamlError> {
         let new_indent_lvl = length / self.indent_length;
         if new_indent_lvl == self.c_indent_lvl ||
            new_indent_lvl == self.c_indent_lvl + 1 ||
@@ -147,11 +227,11 @@ impl Parser {
             self.c_indent_lvl = new_indent_lvl;
             Ok(())
         } else {
-            Err(error::indent_level_much_deeper(self.c_line, new_indent_lvl - self.c_indent_lvl))
+            Err(error::indent_level_much_deeper(self.span(), new_indent_lvl - self.c_indent_lvl))
         }
     }
 
-    fn check_indent(&mut self) -> Result<(), ~str> {
+    fn check_indent(&mut self) -> Result<(), HamlError> {
         match self.tokens.get(0) {
             &token::INDENT(c, l) => {
                 if self.indent_length == 0 {
@@ -161,21 +241,43 @@ impl Parser {
                 try!(self.mix_space_tab_indent());
                 try!(self.inconsistent_indent(c, l));
                 try!(self.indent_level(l));
-                self.tokens.shift();
+                self.next();
                 Ok(())
             },
             _                   => Ok(())
         }
     }
 
-    fn check_attributes(&mut self) -> Result<(), ~str> {
-        Ok(())
+    fn check_attributes(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        // the closing token depends on the attributes style: `{}` or `()`
+        let closing = match self.next() {
+            Some(token::LBRACE)  => token::RBRACE,
+            Some(token::LPARENT) => token::RPARENT,
+            _                    => return Err(error::invalid_attributes(self.last_span))
+        };
+        loop {
+            let name = match self.next() {
+                Some(ref t) if *t == closing => return Ok(()),
+                Some(token::ATTRIBUTE(name)) => name,
+                _                            => {
+                    return Err(error::invalid_attributes(self.last_span))
+                }
+            };
+            if self.tokens.get(0) == &token::RARROW { self.next(); }
+            match self.next() {
+                Some(token::STR(value)) => {
+                    let parts = try!(self.compile(&interpolation::parse(value.as_slice())));
+                    data.attributes.merge_parts(name, parts)
+                },
+                _                       => return Err(error::invalid_attributes(self.last_span))
+            }
+        }
     }
 
-    fn check_tag(&mut self, data: &mut DCollector) -> Result<(), ~str> {
-        fn is_id_or_class_valid(name: &~str, line: u32) -> Result<(), ~str> {
+    fn check_tag(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        fn is_id_or_class_valid(name: &~str, span: Span) -> Result<(), HamlError> {
             if name.len() == 0 {
-                Err(error::illegal_element_class_id(line))
+                Err(error::illegal_element_class_id(span))
             } else {
                 Ok(())
             }
@@ -185,36 +287,60 @@ impl Parser {
                 if name.len() != 0 {
                     data.tag = name.to_owned();
                 } else {
-                    return Err(error::invalid_tag(self.c_line, "%".to_owned()))
+                    return Err(error::invalid_tag(self.span(), "%".to_owned()))
                 }
             },
             &token::ID(ref name)    => {
-                try!(is_id_or_class_valid(name, self.c_line));
+                try!(is_id_or_class_valid(name, self.span()));
                 data.attributes.insert("id".to_owned(), vec!(name.to_owned()));
             },
             &token::CLASS(ref name) => {
-                try!(is_id_or_class_valid(name, self.c_line));
-                data.attributes.insert_or_update_with("class".to_owned(), vec!(name.to_owned()), |_, v| {
-                    v.push(name.to_owned());
-                });
+                try!(is_id_or_class_valid(name, self.span()));
+                data.attributes.push_value("class".to_owned(), name.to_owned());
             },
             _ => {}
         }
-        try!(self.check_attributes());
         Ok(())
     }
 
-    fn check_illegal_nesting(&self, data: &DCollector) -> Result<(), ~str> {
-        match self.tokens.get(0) {
+    // the object is resolved when the template is rendered
+    fn check_object_ref(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        // skip the opening bracket
+        self.next();
+        let name = match self.next() {
+            Some(token::VAR(name)) => name,
+            _                      => return Err(error::invalid_object_ref(self.last_span))
+        };
+        let prefix = match self.next() {
+            Some(token::STR(prefix)) => {
+                match self.next() {
+                    Some(token::RBRACKET) => Some(prefix),
+                    _                     => return Err(error::invalid_object_ref(self.last_span))
+                }
+            },
+            Some(token::RBRACKET)    => None,
+            _                        => return Err(error::invalid_object_ref(self.last_span))
+        };
+        data.object_ref = Some((name, prefix));
+        Ok(())
+    }
+
+    fn check_illegal_nesting(&self, data: &DCollector) -> Result<(), HamlError> {
+        // blank lines (and lines joined by a multiline) don't change the nesting
+        let next = match self.tokens.iter().position(|t| *t != token::EOL) {
+            Some(pos) => pos,
+            None      => return Ok(())
+        };
+        match self.tokens.get(next) {
             &token::INDENT(_, l) => {
-                if data.content != "".to_owned() {
+                if !data.content.is_empty() || data.partial.is_some() {
                     if l > (self.indent_length * self.c_indent_lvl) && (data.tag != "".to_owned() ||
                        !data.attributes.is_empty()) {
-                        Err(error::illegal_nesting(self.c_line, data.tag.to_owned()))
+                        Err(error::illegal_nesting(data.span, data.tag.to_owned()))
                     } else if l > (self.indent_length * self.c_indent_lvl) &&
                               data.tag_type != HamlComment && data.is_plaintext() {
                               // (data.tag == ~"" && data.attributes.is_empty()) {
-                        Err(error::illegal_plain_text_nesting(self.c_line))
+                        Err(error::illegal_plain_text_nesting(data.span))
                     } else {
                         Ok(())
                     }
@@ -226,15 +352,184 @@ impl Parser {
         }
     }
 
+    // a tag ending with `/` or a void tag can't have any content
+    fn check_empty_tag(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        if data.tag_type != Tag || !(data.self_closing || self.opts.is_void(data.tag.as_slice())) {
+            return Ok(())
+        }
+        if !data.content.is_empty() || data.partial.is_some() {
+            return Err(error::no_content_on_empty_tag(data.span))
+        }
+        let next = match self.tokens.iter().position(|t| *t != token::EOL) {
+            Some(pos) => pos,
+            None      => return Ok(())
+        };
+        match self.tokens.get(next) {
+            &token::INDENT(_, l) if l > self.indent_length * self.c_indent_lvl => {
+                Err(error::no_content_on_empty_tag(data.span))
+            },
+            _ => { data.self_closing = true; Ok(()) }
+        }
+    }
+
     fn check_haml_comment(&mut self, data: &mut DCollector) {
         data.tag_type = HamlComment;
-        data.content = "stuff".to_owned();
-        self.tokens.shift();
+        data.content = vec!(Literal("stuff".to_owned()));
+        self.next();
     }
 
     fn check_html_comment(&mut self, data: &mut DCollector) {
         data.tag_type = HtmlComment;
-        self.tokens.shift();
+        self.next();
+    }
+
+    fn check_conditional_comment(&mut self, data: &mut DCollector, condition: ~str,
+                                 revealed: bool) {
+        data.tag_type = if revealed { RevealedComment } else { ConditionalComment };
+        data.condition = condition;
+        self.next();
+    }
+
+    // `!!!` can be followed by a specific format, e.g. `!!! Strict` or `!!! XML utf-8`
+    fn check_doctype(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        if self.content_started {
+            return Err(error::illegal_doctype(self.span()))
+        }
+        self.next();
+        data.tag_type = Header;
+        match self.tokens.get(0).clone() {
+            token::PLAIN_TEXT(spec) => { data.tag = spec; self.next(); },
+            _                       => {}
+        }
+        Ok(())
+    }
+
+    fn check_filter(&mut self, data: &mut DCollector, name: ~str,
+                    content: ~str) -> Result<(), HamlError> {
+        if !filter::is_filter(name.as_slice()) {
+            return Err(error::unknown_filter(self.span(), name))
+        }
+        data.tag_type = Filter;
+        data.tag = name;
+        data.content = try!(self.compile(&interpolation::parse(content.as_slice())));
+        self.next();
+        Ok(())
+    }
+
+    // the expressions are checked when parsing and evaluated when rendering
+    fn compile(&self, parts: &Vec<TextPart>) -> Result<Vec<TextPart>, HamlError> {
+        match interpolation::compile(parts) {
+            Ok(parts) => Ok(parts),
+            Err(msg)  => Err(error::invalid_expression(self.span(), msg))
+        }
+    }
+
+    // the line after `-` is a conditional or a loop, its nested lines are the body
+    fn check_silent_script(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        self.next();
+        let span = self.span();
+        let code = match self.tokens.get(0).clone() {
+            token::PLAIN_TEXT(code) => { self.next(); code },
+            _                       => "".to_owned()
+        };
+        let (keyword, rest) = match code.find(|c: char| c.is_whitespace()) {
+            Some(i) => (code.slice_to(i), code.slice_from(i).trim()),
+            None    => (code.as_slice(), "")
+        };
+        let kind = match keyword {
+            "if"     => dom_tree::If,
+            "unless" => dom_tree::Unless,
+            "elsif"  => dom_tree::Elsif,
+            "else"   => dom_tree::Else,
+            "for"    => {
+                return match rest.find_str(" in ") {
+                    Some(i) => self.check_loop(data, span, rest.slice_to(i),
+                                               rest.slice_from(i + 4)),
+                    None    => Err(error::invalid_loop(span))
+                }
+            },
+            // `items.each do |item|`, the last `.each` followed by a space is the loop
+            // so the collection can have fields like `user.eachers`
+            _        => match code.match_indices(".each").filter(|&(_, end)| {
+                code.slice_from(end).starts_with(" ") || code.slice_from(end).starts_with("\t")
+            }).last() {
+                Some((i, _)) => {
+                    let block = code.slice_from(i + 5).trim();
+                    if !block.starts_with("do") { return Err(error::invalid_loop(span)) }
+                    let vars = block.slice_from(2).trim();
+                    if vars.len() < 2 || !vars.starts_with("|") || !vars.ends_with("|") {
+                        return Err(error::invalid_loop(span))
+                    }
+                    return self.check_loop(data, span, vars.slice(1, vars.len() - 1),
+                                           code.slice_to(i))
+                },
+                None         => return Err(error::unknown_silent_script(span, code.clone()))
+            }
+        };
+        data.expr = match kind {
+            dom_tree::Else if rest.len() == 0 => None,
+            dom_tree::Else                    => {
+                return Err(error::invalid_expression(span, "else has no condition".to_owned()))
+            },
+            _                                 => Some(try!(parse_expression(span, rest)))
+        };
+        data.tag_type = SilentScript(kind);
+        Ok(())
+    }
+
+    // the partial is loaded and rendered with its locals at render time
+    fn check_partial(&self, data: &mut DCollector, source: &str) -> Result<(), HamlError> {
+        match expr::parse_partial(source) {
+            Ok((name, locals)) if data.tag_type == Tag => {
+                data.partial = Some(Item::partial(name, locals));
+                Ok(())
+            },
+            Ok((name, locals)) => {
+                data.tag = name;
+                data.locals = locals;
+                data.tag_type = Partial;
+                Ok(())
+            },
+            Err(msg)           => Err(error::invalid_expression(self.span(), msg))
+        }
+    }
+
+    // one variable for the elements of a list, two for the keys and the values of a map
+    fn check_loop(&self, data: &mut DCollector, span: Span, vars: &str,
+                  collection: &str) -> Result<(), HamlError> {
+        let vars: Vec<~str> = vars.split(',').map(|v| v.trim().to_owned()).collect();
+        if vars.len() > 2 || !vars.iter().all(|v| is_identifier(v.as_slice())) {
+            return Err(error::invalid_loop(span))
+        }
+        data.expr = Some(try!(parse_expression(span, collection)));
+        data.vars = vars;
+        data.tag_type = SilentScript(dom_tree::For);
+        Ok(())
+    }
+
+    // an `elsif` or an `else` follows an `if`, an `unless` or an `elsif` at the same level,
+    // an `else` can also follow a loop
+    fn check_branch(&mut self, data: &DCollector) -> Result<(), HamlError> {
+        let keyword = match data.tag_type {
+            SilentScript(dom_tree::Elsif) => "elsif",
+            SilentScript(dom_tree::Else)  => "else",
+            _                             => return Ok(())
+        };
+        while self.c_indent_lvl < self.dom_tree.get_current_lvl() {
+            self.dom_tree.back();
+        }
+        match (self.dom_tree.get_last_child().map(|item| item.get_tag_type()), keyword) {
+            (Some(dom_tree::If), _)
+            | (Some(dom_tree::Elsif), _)
+            | (Some(dom_tree::Unless), "else")
+            | (Some(dom_tree::For), "else") => Ok(()),
+            (Some(dom_tree::Else), _)       => {
+                Err(error::branch_after_else(data.span, keyword.to_owned()))
+            },
+            _                               => {
+                Err(error::dangling_else(data.span, keyword.to_owned()))
+            }
+        }
     }
 
     fn insert_in_tree(&mut self, data: DCollector) {
@@ -247,30 +542,71 @@ impl Parser {
         let item = match data.tag_type {
             Unknown => {
                 // Just plain text
-                if !data.is_empty() { Item::plain_text(data.content.clone()) }
+                if !data.is_empty() {
+                    Item::plain_text(data.content.clone())
+                        .with_escaping(data.escape)
+                        .with_preserve(data.preserve)
+                }
                 else { return } // empty text
             },
+            Tag if data.self_closing => {
+                Item::self_closing(data.tag.clone(), data.attributes.clone())
+                    .with_whitespace_removal(data.remove_outer_ws, data.remove_inner_ws)
+                    .with_object_ref(data.object_ref.clone())
+            },
             Tag        => {
-                if data.is_block() {
+                let item = if data.is_block() {
                     // Block
                     Item::block(data.tag.clone(), data.attributes.clone())
                 } else {
                     // Inline Block
                     Item::inline(data.tag.clone(), data.attributes.clone(), data.content.clone())
-                }
+                        .with_escaping(data.escape)
+                        .with_preserve(data.preserve)
+                };
+                item.with_whitespace_removal(data.remove_outer_ws, data.remove_inner_ws)
+                    .with_object_ref(data.object_ref.clone())
             },
             HamlComment => Item::haml_comment(),
             HtmlComment => Item::html_comment(data.content.clone()),
-            Header      => { Item::plain_text("".to_owned()) }
+            ConditionalComment => Item::conditional_comment(data.condition.clone(),
+                                                            data.content.clone(), false),
+            RevealedComment    => Item::conditional_comment(data.condition.clone(),
+                                                            data.content.clone(), true),
+            Filter      => Item::filter(data.tag.clone(), data.content.clone()),
+            Header      => Item::header(data.tag.clone()),
+            SilentScript(dom_tree::For) => {
+                Item::for_loop(data.vars.clone(), data.expr.clone().unwrap())
+            },
+            SilentScript(kind) => Item::branch(kind, data.expr.clone()),
+            Partial     => Item::partial(data.tag.clone(), data.locals.clone())
         };
-        insert(item, &mut self.dom_tree, self.c_indent_lvl);
+        // only headers and haml comments can precede a header
+        match data.tag_type {
+            Header | HamlComment => {},
+            _                    => self.content_started = true
+        }
+        insert(item.with_span(data.span), &mut self.dom_tree, self.c_indent_lvl);
+        match data.partial {
+            Some(ref partial) => {
+                self.dom_tree.insert_and_back(partial.clone().with_span(data.span));
+            },
+            None              => {}
+        }
     }
 
-    fn finalize_item_on_new_line(&mut self, data: DCollector) -> Result<(), ~str> {
-        self.tokens.shift();
+    // check the complete line and insert it in the tree
+    fn finalize_item(&mut self, mut data: DCollector) -> Result<(), HamlError> {
+        try!(self.check_empty_tag(&mut data));
         try!(self.check_illegal_nesting(&data));
-        self.c_line += 1;
+        try!(self.check_branch(&data));
         self.insert_in_tree(data);
+        Ok(())
+    }
+
+    fn finalize_item_on_new_line(&mut self, data: DCollector) -> Result<(), HamlError> {
+        try!(self.finalize_item(data));
+        self.next();
         // if no indent after a new line reset indent_lvl
         match self.tokens.get(0) {
             &token::INDENT(_, _) => {}, // there is indent next so no reset
@@ -281,40 +617,193 @@ impl Parser {
         Ok(())
     }
 
-    pub fn execute(&mut self, tokens: Vec<Token>) -> Result<DomTree, ~str> {
+    // skip the rest of the line in error and the lines nested in it, the parsing
+    // starts again on the next line at a known indent level
+    fn resynchronize(&mut self) {
+        loop {
+            loop {
+                match self.tokens.get(0) {
+                    &token::EOF => return,
+                    &token::EOL => { self.next(); break },
+                    _           => { self.next(); }
+                }
+            }
+            match self.tokens.get(0).clone() {
+                token::EOL          => {}, // blank line
+                token::EOF          => return,
+                token::INDENT(c, l) => {
+                    let mixed = match self.tokens.get(1) {
+                        &token::INDENT(_, _) => true,
+                        _                    => false
+                    };
+                    if !mixed && self.indent_length != 0 && c == self.indent_char &&
+                       l % self.indent_length == 0 && l / self.indent_length <= self.c_indent_lvl {
+                        return
+                    }
+                },
+                _                   => {
+                    self.c_indent_lvl = 0;
+                    return
+                }
+            }
+        }
+    }
+
+    // data collects the tokens of the current line
+    fn parse_token(&mut self, data: &mut DCollector) -> Result<(), HamlError> {
+        // the item starts at its first token
+        if data.tag_type == Unknown && data.is_empty() { data.span = self.span(); }
+        match self.tokens.get(0).clone() {
+            token::INDENT(_, _)      => try!(self.check_indent()),
+            token::TAG(_)
+            | token::ID(_)
+            | token::CLASS(_)        => {
+                try!(self.check_tag(data));
+                self.next();
+                data.tag_type = Tag;
+            },
+            token::LBRACE
+            | token::LPARENT         => {
+                try!(self.check_attributes(data));
+                data.tag_type = Tag;
+            },
+            token::LBRACKET          => {
+                try!(self.check_object_ref(data));
+                data.tag_type = Tag;
+            },
+            token::REMOVE_WS_OUTER   => { data.remove_outer_ws = true; self.next(); },
+            token::REMOVE_WS_INNER   => { data.remove_inner_ws = true; self.next(); },
+            token::CLOSING_EMPTY     => { data.self_closing = true; self.next(); },
+            token::PLAIN_TEXT(ref s) if data.script
+                                        && (data.tag_type == Unknown || data.tag_type == Tag)
+                                        && s.trim().starts_with("render ") => {
+                try!(self.check_partial(data, s.trim().slice_from(7)));
+                self.next();
+            },
+            token::PLAIN_TEXT(ref s) => {
+                // the content of a script is an expression
+                data.content = if data.script {
+                    try!(self.compile(&vec!(Interpolation(s.trim().to_owned()))))
+                } else { vec!(Literal(s.clone())) };
+                self.next();
+            },
+            token::ASSIGN            => { data.script = true; self.next(); },
+            token::PRESERVE_ASSIGN   => {
+                data.script = true;
+                data.preserve = true;
+                self.next();
+            },
+            token::ESCAPED_ASSIGN    => {
+                data.script = true;
+                data.escape = Escaped;
+                self.next();
+            },
+            token::UNESCAPED_ASSIGN  => {
+                data.script = true;
+                data.escape = Unescaped;
+                self.next();
+            },
+            token::ESCAPED           => { data.escape = Escaped; self.next(); },
+            token::UNESCAPED         => { data.escape = Unescaped; self.next(); },
+            token::INTERPOLATED(ref parts) => {
+                data.content = try!(self.compile(parts));
+                self.next();
+            },
+            token::EOL               => {
+                let line = mem::replace(data, DCollector::new());
+                try!(self.finalize_item_on_new_line(line));
+            },
+            token::FILTER(name, content) => try!(self.check_filter(data, name, content)),
+            token::DOCTYPE           => try!(self.check_doctype(data)),
+            token::HAML_COMMENT      => { self.check_haml_comment(data); },
+            token::SILENT_SCRIPT     => try!(self.check_silent_script(data)),
+            token::HTML_COMMENT      => { self.check_html_comment(data); },
+            token::CONDITIONAL_COMMENT(condition) => {
+                self.check_conditional_comment(data, condition, false)
+            },
+            token::REVEALED_COMMENT(condition)    => {
+                self.check_conditional_comment(data, condition, true)
+            },
+            _                        => { self.next(); }
+        }
+        Ok(())
+    }
+
+    pub fn execute(&mut self, tokens: Vec<Token>) -> Result<DomTree, HamlError> {
+        let spans = line_spans(&tokens);
+        self.execute_with_spans(tokens, spans)
+    }
+
+    pub fn execute_with_spans(&mut self, tokens: Vec<Token>,
+                              spans: Vec<Span>) -> Result<DomTree, HamlError> {
         self.tokens = tokens;
+        self.spans = spans;
         try!(self.check_indent_on_first_line());
         let mut data: DCollector = DCollector::new();
         while self.tokens.get(0) != &token::EOF {
-            match self.tokens.get(0).clone() {
-                token::INDENT(_, _)      => try!(self.check_indent()),
-                token::TAG(_)
-                | token::ID(_)
-                | token::CLASS(_)        => {
-                    try!(self.check_tag(&mut data));
-                    self.tokens.shift();
-                    data.tag_type = Tag;
-                },
-                token::PLAIN_TEXT(ref s) => { data.content = s.clone(); self.tokens.shift(); },
-                token::EOL               => {
-                    try!(self.finalize_item_on_new_line(data));
+            try!(self.parse_token(&mut data));
+        }
+        // the last line has no new line
+        try!(self.finalize_item(data));
+
+        Ok(self.dom_tree.clone())
+    }
+
+    // parse the whole document even if there are errors, return all of them
+    // with the tree built from the valid lines
+    pub fn execute_recovering(&mut self, tokens: Vec<Token>,
+                              spans: Vec<Span>) -> (DomTree, Vec<HamlError>) {
+        self.tokens = tokens;
+        self.spans = spans;
+        let mut errors = Vec::new();
+        match self.check_indent_on_first_line() {
+            Err(e) => { errors.push(e); self.resynchronize(); },
+            Ok(_)  => {}
+        }
+        let mut data: DCollector = DCollector::new();
+        while self.tokens.get(0) != &token::EOF {
+            match self.parse_token(&mut data) {
+                Err(e) => {
+                    errors.push(e);
+                    self.resynchronize();
                     data = DCollector::new();
                 },
-                token::HAML_COMMENT      => { self.check_haml_comment(&mut data); },
-                token::HTML_COMMENT      => { self.check_html_comment(&mut data); },
-                _                        => { self.tokens.shift(); }
+                Ok(_)  => {}
             }
         }
+        // the last line has no new line
+        match self.finalize_item(data) {
+            Err(e) => errors.push(e),
+            Ok(_)  => {}
+        }
 
-        Ok(self.dom_tree.clone())
+        (self.dom_tree.clone(), errors)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use collections::HashMap;
     use token;
-    use format::Html5;
-    use parser::{Parser, DCollector};
+    use format::{Html5, Xhtml, XHTML_1_0_Strict, XHTML_1_0_Transitional};
+    use parser::{Parser, DCollector, line_spans};
+    use interpolation::Literal;
+    use loader::MemoryLoader;
+    use options::Options;
+    use context::{Context, HamlObjectRef, object_ref_attributes};
+    use span::Span;
+    use error::{HamlError, IllegalElementClassId, IllegalDoctype, IndentLevelMuchDeeper,
+                IllegalIndentAtBegin, UnknownObjectRef, InvalidExpression, DanglingElse,
+                BranchAfterElse, InvalidLoop, NoContentOnEmptyTag};
+
+    struct User {
+        id: Option<u32>
+    }
+
+    impl HamlObjectRef for User {
+        fn object_ref_type(&self) -> ~str { "user".to_owned() }
+        fn object_ref_key(&self) -> Option<~str> { self.id.map(|id| id.to_str()) }
+    }
 
     #[test]
     fn document_beginning_with_indent_is_invalid() {
@@ -509,6 +998,15 @@ mod test {
        assert_err!(parser.execute(tokens))
     }
 
+    #[test]
+    fn content_on_a_multiline_and_nested_is_illegal() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("tag".to_owned()), token::PLAIN_TEXT("Hello world".to_owned()),
+                          token::EOL, token::EOL, token::INDENT(' ', 2),
+                          token::TAG("tag2".to_owned()), token::EOL, token::EOF);
+       assert_err!(parser.execute(tokens))
+    }
+
     #[test]
     fn plain_text_can_be_followed_by_plain_text() {
         let mut parser = Parser::new(Html5);
@@ -527,6 +1025,498 @@ mod test {
        assert_ok!(parser.execute(tokens))
     }
 
+    #[test]
+    fn attribute_hash_is_valid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("a".to_owned()), token::LBRACE,
+                          token::ATTRIBUTE("href".to_owned()), token::RARROW,
+                          token::STR("/x".to_owned()), token::ATTRIBUTE("title".to_owned()),
+                          token::STR("y".to_owned()), token::RBRACE, token::EOL, token::EOF);
+        assert_ok!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn attribute_without_value_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("a".to_owned()), token::LBRACE,
+                          token::ATTRIBUTE("href".to_owned()), token::RARROW, token::RBRACE,
+                          token::EOL, token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn html_style_and_hash_attributes_can_be_mixed() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("img".to_owned()), token::LPARENT,
+                          token::ATTRIBUTE("src".to_owned()), token::STR("a.png".to_owned()),
+                          token::RPARENT, token::LBRACE, token::ATTRIBUTE("alt".to_owned()),
+                          token::STR("x".to_owned()), token::RBRACE, token::EOL, token::EOF);
+        assert_ok!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn html_style_attributes_cannot_be_closed_by_a_brace() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("img".to_owned()), token::LPARENT,
+                          token::ATTRIBUTE("src".to_owned()), token::STR("a.png".to_owned()),
+                          token::RBRACE, token::EOL, token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn object_reference_is_parsed_with_prefix() {
+        let mut parser = Parser::new(Html5);
+        let mut data = DCollector::new();
+        parser.tokens = vec!(token::LBRACKET, token::VAR("user".to_owned()),
+                             token::STR("admin".to_owned()), token::RBRACKET, token::EOF);
+        assert_ok!(parser.check_object_ref(&mut data))
+        assert_eq!(Some(("user".to_owned(), Some("admin".to_owned()))), data.object_ref)
+    }
+
+    #[test]
+    fn object_reference_generates_class_and_id() {
+        let mut parser = Parser::new(Html5);
+        let mut ctx = Context::new();
+        ctx.set_object_ref("user", ~User { id: Some(42) } as ~HamlObjectRef);
+        let tokens = vec!(token::TAG("div".to_owned()), token::CLASS("c".to_owned()),
+                          token::LBRACKET, token::VAR("user".to_owned()),
+                          token::STR("admin".to_owned()), token::RBRACKET, token::EOL, token::EOF);
+        assert_eq!(Ok("<div class='c admin_user' id='admin_user_42'></div>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn object_reference_without_key_is_new() {
+        let (class, id) = object_ref_attributes(&User { id: None }, None);
+        assert_eq!("user".to_owned(), class)
+        assert_eq!("new_user".to_owned(), id)
+    }
+
+    #[test]
+    fn unknown_object_reference_is_a_render_error() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("div".to_owned()), token::LBRACKET,
+                          token::VAR("user".to_owned()), token::RBRACKET, token::EOL, token::EOF);
+        let dom_tree = parser.execute(tokens).unwrap();
+        assert_eq!(Err(HamlError::new(UnknownObjectRef("user".to_owned()), Span::line(1))),
+                   dom_tree.render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn attribute_class_is_merged_with_class_shortcut() {
+        let mut data = DCollector::new();
+        data.attributes.insert("class".to_owned(), vec!("a".to_owned()));
+        data.attributes.merge("class".to_owned(), "b".to_owned());
+        assert_eq!(Some(&vec!(vec!(Literal("a".to_owned())), vec!(Literal("b".to_owned())))),
+                   data.attributes.find("class"))
+    }
+
+    #[test]
+    fn attribute_id_is_joined_with_id_shortcut() {
+        let mut data = DCollector::new();
+        data.attributes.insert("id".to_owned(), vec!("a".to_owned()));
+        data.attributes.merge("id".to_owned(), "b".to_owned());
+        assert_eq!(Some(&vec!(vec!(Literal("a_b".to_owned())))), data.attributes.find("id"))
+    }
+
+    #[test]
+    fn known_filter_is_valid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::FILTER("javascript".to_owned(), "var a;".to_owned()), token::EOL,
+                          token::EOF);
+        assert_ok!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn unknown_filter_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::FILTER("ruby".to_owned(), "puts 1".to_owned()), token::EOL,
+                          token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn script_content_is_evaluated_and_escaped() {
+        let mut parser = Parser::new(Html5);
+        let mut ctx = Context::new();
+        ctx.set_value("a", "<b>".to_owned());
+        let tokens = vec!(token::TAG("p".to_owned()), token::ESCAPED_ASSIGN,
+                          token::PLAIN_TEXT("a".to_owned()), token::EOL,
+                          token::UNESCAPED_ASSIGN, token::PLAIN_TEXT("a".to_owned()), token::EOL,
+                          token::EOF);
+        assert_eq!(Ok("<p>&lt;b&gt;</p>\n<b>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn script_expression_is_evaluated() {
+        let mut parser = Parser::new(Html5);
+        let mut ctx = Context::new();
+        ctx.set("items", vec!("a", "b"));
+        let tokens = vec!(token::TAG("p".to_owned()), token::ASSIGN,
+                          token::PLAIN_TEXT(" items.length * 2 ".to_owned()), token::EOL,
+                          token::EOF);
+        assert_eq!(Ok("<p>4</p>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn invalid_script_expression() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::ASSIGN,
+                          token::PLAIN_TEXT("a +".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Err(HamlError::new(InvalidExpression("unexpected end of expression"
+                                                        .to_owned()), Span::line(1))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn attribute_interpolations_are_compiled_when_parsing() {
+        let mut ctx = Context::new();
+        ctx.set("id", 21i);
+        let tokens = vec!(token::TAG("a".to_owned()), token::LBRACE,
+                          token::ATTRIBUTE("href".to_owned()),
+                          token::STR("/u/#{id * 2}".to_owned()),
+                          token::RBRACE, token::EOL, token::EOF);
+        let dom_tree = Parser::new(Html5).execute(tokens).unwrap();
+        assert_eq!(Ok("<a href='/u/42'></a>\n".to_owned()),
+                   dom_tree.render(&Options::new(Html5), &ctx))
+        let tokens = vec!(token::TAG("a".to_owned()), token::LBRACE,
+                          token::ATTRIBUTE("href".to_owned()), token::STR("#{id +}".to_owned()),
+                          token::RBRACE, token::EOL, token::EOF);
+        assert_eq!(Err(HamlError::new(InvalidExpression("unexpected end of expression"
+                                                        .to_owned()), Span::line(1))),
+                   Parser::new(Html5).execute(tokens).map(|_| ()))
+    }
+
+    fn branch_tokens(first: &str, second: &str) -> Vec<token::Token> {
+        vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT(first.to_owned()), token::EOL,
+             token::INDENT(' ', 2), token::TAG("a".to_owned()), token::EOL,
+             token::SILENT_SCRIPT, token::PLAIN_TEXT(second.to_owned()), token::EOL,
+             token::INDENT(' ', 2), token::TAG("b".to_owned()), token::EOL,
+             token::SILENT_SCRIPT, token::PLAIN_TEXT("else".to_owned()), token::EOL,
+             token::INDENT(' ', 2), token::TAG("c".to_owned()), token::EOL, token::EOF)
+    }
+
+    #[test]
+    fn only_the_first_true_branch_is_rendered() {
+        let mut ctx = Context::new();
+        let opts = Options::new(Html5);
+        let mut parser = Parser::new(Html5);
+        let dom_tree = parser.execute(branch_tokens("if x > 1", "elsif x > 0")).unwrap();
+        ctx.set("x", 2i);
+        assert_eq!(Ok("<a></a>\n".to_owned()), dom_tree.render(&opts, &ctx))
+        ctx.set("x", 1i);
+        assert_eq!(Ok("<b></b>\n".to_owned()), dom_tree.render(&opts, &ctx))
+        ctx.set("x", 0i);
+        assert_eq!(Ok("<c></c>\n".to_owned()), dom_tree.render(&opts, &ctx))
+    }
+
+    #[test]
+    fn unless_renders_when_the_condition_is_false() {
+        let mut ctx = Context::new();
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT("unless user".to_owned()),
+                          token::EOL, token::INDENT(' ', 2), token::TAG("a".to_owned()), token::EOL,
+                          token::SILENT_SCRIPT, token::PLAIN_TEXT("else".to_owned()), token::EOL,
+                          token::INDENT(' ', 2), token::TAG("b".to_owned()), token::EOL,
+                          token::EOF);
+        let dom_tree = parser.execute(tokens).unwrap();
+        assert_eq!(Ok("<a></a>\n".to_owned()), dom_tree.render(&Options::new(Html5), &ctx))
+        ctx.set("user", "john");
+        assert_eq!(Ok("<b></b>\n".to_owned()), dom_tree.render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn elsif_after_unless_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT("unless a".to_owned()),
+                          token::EOL, token::SILENT_SCRIPT,
+                          token::PLAIN_TEXT("elsif b".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Err(HamlError::new(DanglingElse("elsif".to_owned()), Span::line(2))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn dangling_else_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::EOL,
+                          token::SILENT_SCRIPT, token::PLAIN_TEXT("else".to_owned()), token::EOL,
+                          token::EOF);
+        assert_eq!(Err(HamlError::new(DanglingElse("else".to_owned()), Span::line(2))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn elsif_after_else_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT("if a".to_owned()), token::EOL,
+                          token::SILENT_SCRIPT, token::PLAIN_TEXT("else".to_owned()), token::EOL,
+                          token::SILENT_SCRIPT, token::PLAIN_TEXT("elsif b".to_owned()),
+                          token::EOL, token::EOF);
+        assert_eq!(Err(HamlError::new(BranchAfterElse("elsif".to_owned()), Span::line(3))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn unknown_silent_script_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT("x = 1".to_owned()),
+                          token::EOL, token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    fn loop_tokens(code: &str) -> Vec<token::Token> {
+        vec!(token::SILENT_SCRIPT, token::PLAIN_TEXT(code.to_owned()), token::EOL,
+             token::INDENT(' ', 2), token::TAG("li".to_owned()), token::ASSIGN,
+             token::PLAIN_TEXT("x".to_owned()), token::EOL,
+             token::SILENT_SCRIPT, token::PLAIN_TEXT("else".to_owned()), token::EOL,
+             token::INDENT(' ', 2), token::TAG("p".to_owned()), token::EOL, token::EOF)
+    }
+
+    #[test]
+    fn for_and_each_loops() {
+        let mut ctx = Context::new();
+        ctx.set("xs", vec!(1i, 2i));
+        let mut user = HashMap::new();
+        user.insert("eachers".to_owned(), vec!(1i, 2i));
+        ctx.set("user", user);
+        for code in ["for x in xs", "xs.each do |x|", "user.eachers.each do |x|"].iter() {
+            let mut parser = Parser::new(Html5);
+            let dom_tree = parser.execute(loop_tokens(*code)).unwrap();
+            assert_eq!(Ok("<li>1</li>\n<li>2</li>\n".to_owned()),
+                       dom_tree.render(&Options::new(Html5), &ctx))
+            assert_eq!(Ok("<p></p>\n".to_owned()),
+                       dom_tree.render(&Options::new(Html5), &Context::new()))
+        }
+    }
+
+    #[test]
+    fn invalid_loops() {
+        for code in ["for x", "for 1 in xs", "for a, b, c in xs", "xs.each |x|",
+                     "user.eachers.each |x|"].iter() {
+            let mut parser = Parser::new(Html5);
+            assert_eq!(Err(HamlError::new(InvalidLoop, Span::line(1))),
+                       parser.execute(loop_tokens(*code)).map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn partial_with_locals() {
+        let mut parser = Parser::new(Html5);
+        let mut loader = MemoryLoader::new();
+        loader.add("shared/card", "%h2= title\n");
+        let mut ctx = Context::new();
+        ctx.set("t", "Hello");
+        let tokens = vec!(token::ASSIGN,
+                          token::PLAIN_TEXT(" render \"shared/card\", title: t".to_owned()),
+                          token::EOL, token::EOF);
+        assert_eq!(Ok("<h2>Hello</h2>\n".to_owned()),
+                   parser.execute(tokens).unwrap()
+                         .render_with_loader(&Options::new(Html5), &ctx, &loader))
+    }
+
+    #[test]
+    fn partial_nested_under_a_tag() {
+        let mut parser = Parser::new(Html5);
+        let mut loader = MemoryLoader::new();
+        loader.add("card", "%p= title\n");
+        let mut ctx = Context::new();
+        ctx.set("t", "Hello");
+        let tokens = vec!(token::TAG("div".to_owned()), token::ASSIGN,
+                          token::PLAIN_TEXT(" render \"card\", title: t".to_owned()),
+                          token::EOL, token::EOF);
+        assert_eq!(Ok("<div>\n  <p>Hello</p>\n</div>\n".to_owned()),
+                   parser.execute(tokens).unwrap()
+                         .render_with_loader(&Options::new(Html5), &ctx, &loader))
+    }
+
+    #[test]
+    fn invalid_partial() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::ASSIGN, token::PLAIN_TEXT("render card".to_owned()),
+                          token::EOL, token::EOF);
+        assert!(parser.execute(tokens).is_err())
+    }
+
+    #[test]
+    fn conditional_comment_wrap_nested_content() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::CONDITIONAL_COMMENT("if lt IE 9".to_owned()), token::EOL,
+                          token::INDENT(' ', 2), token::TAG("p".to_owned()),
+                          token::PLAIN_TEXT("old".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Ok("<!--[if lt IE 9]>\n  <p>old</p>\n<![endif]-->\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn revealed_comment_wrap_nested_content() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::REVEALED_COMMENT("if !IE".to_owned()), token::EOL,
+                          token::INDENT(' ', 2), token::TAG("p".to_owned()),
+                          token::PLAIN_TEXT("new".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Ok("<!--[if !IE]><!-->\n  <p>new</p>\n<!--<![endif]-->\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn doctype_is_rendered_for_the_html_format() {
+        let mut parser = Parser::new(Xhtml);
+        let tokens = vec!(token::DOCTYPE, token::PLAIN_TEXT("Strict".to_owned()), token::EOL,
+                          token::TAG("html".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Ok(format!("{}\n<html></html>\n", XHTML_1_0_Strict)),
+                   parser.execute(tokens).unwrap().render(&Options::new(Xhtml), &Context::new()))
+    }
+
+    #[test]
+    fn xml_prolog_can_be_followed_by_doctype() {
+        let mut parser = Parser::new(Xhtml);
+        let tokens = vec!(token::DOCTYPE, token::PLAIN_TEXT("XML iso-8859-1".to_owned()),
+                          token::EOL, token::DOCTYPE, token::EOL, token::EOF);
+        assert_eq!(Ok(format!("<?xml version='1.0' encoding='iso-8859-1' ?>\n{}\n",
+                              XHTML_1_0_Transitional)),
+                   parser.execute(tokens).unwrap().render(&Options::new(Xhtml), &Context::new()))
+    }
+
+    #[test]
+    fn doctype_after_content_is_invalid() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("html".to_owned()), token::EOL, token::DOCTYPE, token::EOL,
+                          token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn void_tag_depends_on_html_format() {
+        let tokens = vec!(token::TAG("br".to_owned()), token::EOL,
+                          token::TAG("img".to_owned()), token::CLOSING_EMPTY, token::EOL,
+                          token::EOF);
+        let mut parser = Parser::new(Xhtml);
+        assert_eq!(Ok("<br />\n<img />\n".to_owned()),
+                   parser.execute(tokens.clone()).unwrap().render(&Options::new(Xhtml),
+                                                                  &Context::new()));
+        let mut parser = Parser::new(Html5);
+        assert_eq!(Ok("<br>\n<img>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn last_line_without_new_line() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::EOL,
+                          token::TAG("br".to_owned()), token::EOF);
+        assert_eq!(Ok("<p></p>\n<br>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &Context::new()))
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("br".to_owned()), token::PLAIN_TEXT("hello".to_owned()),
+                          token::EOF);
+        assert_eq!(Err(HamlError::new(NoContentOnEmptyTag, Span::line(1))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn explicit_self_closing_tag() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("foo".to_owned()), token::CLOSING_EMPTY, token::EOL,
+                          token::EOF);
+        assert_eq!(Ok("<foo>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn added_void_tag() {
+        let mut opts = Options::new(Xhtml);
+        opts.void.push("foo".to_owned());
+        let mut parser = Parser::with_options(opts.clone());
+        let tokens = vec!(token::TAG("foo".to_owned()), token::EOL, token::EOF);
+        assert_eq!(Ok("<foo />\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&opts, &Context::new()))
+    }
+
+    #[test]
+    fn inline_content_on_void_tag() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("br".to_owned()), token::PLAIN_TEXT("hello".to_owned()),
+                          token::EOL, token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn nested_content_on_self_closing_tag() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::CLOSING_EMPTY, token::EOL,
+                          token::INDENT(' ', 2), token::PLAIN_TEXT("hello".to_owned()), token::EOL,
+                          token::EOF);
+        assert_err!(parser.execute(tokens))
+    }
+
+    #[test]
+    fn error_points_to_the_token_column() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::CLASS("".to_owned()), token::EOL,
+                          token::EOF);
+        let spans = vec!(Span::new(1, 1, 0, 2), Span::new(1, 3, 2, 1), Span::new(1, 4, 3, 1),
+                         Span::new(2, 1, 4, 0));
+        assert_eq!(Err(HamlError::new(IllegalElementClassId, Span::new(1, 3, 2, 1))),
+                   parser.execute_with_spans(tokens, spans).map(|_| ()))
+    }
+
+    #[test]
+    fn error_line_without_spans() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::EOL, token::DOCTYPE, token::EOL,
+                          token::EOF);
+        assert_eq!(Err(HamlError::new(IllegalDoctype, Span::line(2))),
+                   parser.execute(tokens).map(|_| ()))
+    }
+
+    #[test]
+    fn recovering_parser_reports_all_errors() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::TAG("p".to_owned()), token::CLASS("".to_owned()), token::EOL,
+                          token::TAG("a".to_owned()), token::EOL,
+                          token::INDENT(' ', 2), token::TAG("b".to_owned()), token::EOL,
+                          token::INDENT(' ', 6), token::TAG("c".to_owned()), token::EOL,
+                          token::INDENT(' ', 2), token::TAG("d".to_owned()), token::EOL,
+                          token::EOF);
+        let spans = line_spans(&tokens);
+        let (dom_tree, errors) = parser.execute_recovering(tokens, spans);
+        assert_eq!(vec!(HamlError::new(IllegalElementClassId, Span::line(1)),
+                        HamlError::new(IndentLevelMuchDeeper(2), Span::line(4))), errors);
+        assert_eq!(Ok("<a>\n  <b></b>\n  <d></d>\n</a>\n".to_owned()),
+                   dom_tree.render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn recovering_parser_skips_lines_nested_in_an_error() {
+        let mut parser = Parser::new(Html5);
+        let tokens = vec!(token::INDENT(' ', 2), token::TAG("a".to_owned()), token::EOL,
+                          token::INDENT(' ', 4), token::TAG("b".to_owned()), token::EOL,
+                          token::TAG("c".to_owned()), token::EOL, token::EOF);
+        let spans = line_spans(&tokens);
+        let (dom_tree, errors) = parser.execute_recovering(tokens, spans);
+        assert_eq!(vec!(HamlError::new(IllegalIndentAtBegin, Span::line(1))), errors);
+        assert_eq!(Ok("<c></c>\n".to_owned()),
+                   dom_tree.render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn attributes_are_rendered_in_source_order() {
+        let tokens = vec!(token::TAG("a".to_owned()), token::ID("x".to_owned()),
+                          token::CLASS("y".to_owned()), token::LBRACE,
+                          token::ATTRIBUTE("href".to_owned()), token::STR("/".to_owned()),
+                          token::RBRACE, token::EOL, token::EOF);
+        let mut parser = Parser::new(Html5);
+        assert_eq!(Ok("<a id='x' class='y' href='/'></a>\n".to_owned()),
+                   parser.execute(tokens.clone()).unwrap().render(&Options::new(Html5),
+                                                                  &Context::new()));
+        let mut opts = Options::new(Html5);
+        opts.sort_attributes = true;
+        let mut parser = Parser::new(Html5);
+        assert_eq!(Ok("<a class='y' href='/' id='x'></a>\n".to_owned()),
+                   parser.execute(tokens).unwrap().render(&opts, &Context::new()))
+    }
+
     #[test]
     fn data_collector_is_empty() {
         let data = DCollector::new();
@@ -539,7 +1529,7 @@ mod test {
     #[test]
     fn data_collector_is_plaintext() {
         let mut data = DCollector::new();
-        data.content = "some content".to_owned();
+        data.content = vec!(Literal("some content".to_owned()));
         assert_false!(data.is_empty())
         assert_false!(data.is_inline())
         assert_false!(data.is_block())
@@ -560,7 +1550,7 @@ mod test {
     fn data_collector_is_inline() {
         let mut data = DCollector::new();
         data.tag = "some_tag".to_owned();
-        data.content = "some content".to_owned();
+        data.content = vec!(Literal("some content".to_owned()));
         assert_false!(data.is_empty())
         assert_true!(data.is_inline())
         assert_false!(data.is_block())
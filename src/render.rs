@@ -0,0 +1,551 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::mem;
+use std::rc::Rc;
+use std::io::{MemReader, Reader};
+use collections::{HashMap, TreeMap};
+
+use context::{Context, object_ref_attributes};
+use dom_tree::{DomTree, Item, Block, Inline, SelfClosing, PlainText, Header, HtmlComment,
+               ConditionalComment, RevealedComment, Filter, If, Unless, Elsif, Else, For, Partial,
+               Escaped, Unescaped, DefaultEscaping};
+use error;
+use error::HamlError;
+use escape::escape_html;
+use expr;
+use expr::Lookup;
+use filter;
+use format;
+use format::Xhtml;
+use input_reader::InputReader;
+use interpolation;
+use lexer::Lexer;
+use loader::TemplateLoader;
+use options::Options;
+use parser::Parser;
+use value::{Value, Nil, Bool, Int, Str, List, Map};
+
+// the values of the render context and the variables of the loops,
+// the innermost loop hides the others
+struct Scope<'a> {
+    ctx: &'a Context,
+    locals: Vec<HashMap<~str, Value>>
+}
+
+impl<'a> Lookup for Scope<'a> {
+    fn lookup_var(&self, name: &str) -> Option<Value> {
+        for locals in self.locals.iter().rev() {
+            match locals.find(&name.to_owned()) {
+                Some(value) => return Some(value.clone()),
+                None        => {}
+            }
+        }
+        self.ctx.lookup_var(name)
+    }
+}
+
+// The rendered html, whitespace can be removed on both side of a tag
+// so the output is buffered before being written.
+struct Output {
+    buf: StrBuf,
+    remove_ws: bool
+}
+
+impl Output {
+    fn new() -> Output {
+        Output {
+            buf: StrBuf::new(),
+            remove_ws: false
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        let s = if self.remove_ws { s.trim_left() } else { s };
+        if s.len() > 0 {
+            self.remove_ws = false;
+            self.buf.push_str(s);
+        }
+    }
+
+    // remove the whitespace at the end of the output and the beginning
+    // of the next push.
+    fn remove_whitespace(&mut self) {
+        let len = self.buf.as_slice().trim_right().len();
+        self.buf.truncate(len);
+        self.remove_ws = true;
+    }
+}
+
+fn parse_template(source: &str, opts: &Options) -> Result<DomTree, HamlError> {
+    let input = ~MemReader::new(Vec::from_slice(source.as_bytes())) as ~Reader;
+    let mut lexer = Lexer::new(InputReader::new(input));
+    let tokens = try!(lexer.execute());
+    let mut parser = Parser::with_options(opts.clone());
+    parser.execute_with_spans(tokens, lexer.get_spans())
+}
+
+// render a dom tree, the same tree can be rendered with many contexts
+pub struct Renderer<'a> {
+    opts: &'a Options,
+    scope: Scope<'a>,
+    out: Output,
+    loader: Option<&'a TemplateLoader>,
+    // the partials being rendered, to detect the recursive ones
+    includes: Vec<~str>,
+    // the partials already parsed, a partial rendered in a loop is parsed once
+    partials: HashMap<~str, Rc<DomTree>>
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(opts: &'a Options, ctx: &'a Context) -> Renderer<'a> {
+        Renderer {
+            opts: opts,
+            scope: Scope { ctx: ctx, locals: Vec::new() },
+            out: Output::new(),
+            loader: None,
+            includes: Vec::new(),
+            partials: HashMap::new()
+        }
+    }
+
+    pub fn with_loader(mut self, loader: &'a TemplateLoader) -> Renderer<'a> {
+        self.loader = Some(loader);
+        self
+    }
+
+    pub fn render(mut self, root: &Item) -> Result<~str, HamlError> {
+        try!(self.render_items(root, "".to_owned()));
+        Ok(self.out.buf.into_owned())
+    }
+
+    // the condition of a branch or the collection of a loop
+    fn eval(&self, item: &Item) -> Result<Value, HamlError> {
+        match *item.get_expr() {
+            Some(ref e) => match expr::eval(e, &self.scope, &self.opts.helpers) {
+                Ok(value) => Ok(value),
+                Err(msg)  => Err(error::eval_error(item.get_span(), msg))
+            },
+            None        => Ok(Bool(true))
+        }
+    }
+
+    fn eval_content(&self, item: &Item, escape: bool) -> Result<~str, HamlError> {
+        match interpolation::eval(item.get_content(), &self.scope, &self.opts.helpers, escape) {
+            Ok(content) => Ok(content),
+            Err(msg)    => Err(error::eval_error(item.get_span(), msg))
+        }
+    }
+
+    // the attributes with the class and the id of the object reference
+    fn format_attributes(&self, item: &Item) -> Result<~str, HamlError> {
+        let mut attributes = item.get_attributes().clone();
+        match *item.get_object_ref() {
+            Some((ref name, ref prefix)) => {
+                let (class, id) = match self.scope.ctx.get_object_ref(name.as_slice()) {
+                    Some(object) => object_ref_attributes(&**object, prefix.clone()),
+                    None         => {
+                        return Err(error::unknown_object_ref(item.get_span(), name.clone()))
+                    }
+                };
+                attributes.merge("class".to_owned(), class);
+                attributes.merge("id".to_owned(), id);
+            },
+            None                         => {}
+        }
+        if self.opts.sort_attributes { attributes = attributes.sorted(); }
+        let mut fmt = StrBuf::new();
+        for &(ref at, ref values) in attributes.iter() {
+            fmt.push_str(format!(" {}=\'", at));
+            for v in values.iter() {
+                let value = match interpolation::eval(v, &self.scope, &self.opts.helpers,
+                                                      false) {
+                    Ok(value) => value,
+                    Err(msg)  => return Err(error::eval_error(item.get_span(), msg))
+                };
+                fmt.push_str(format!("{} ", escape_html(value)));
+            }
+            if values.len() > 0 { unsafe { fmt.pop_byte(); } }
+            fmt.push_str("\'");
+        }
+        Ok(fmt.into_owned())
+    }
+
+    // the values bound to the loop variables for each element of the collection,
+    // a map gives its keys and its values
+    fn loop_entries(&self, item: &Item) -> Result<Vec<Vec<Value>>, HamlError> {
+        match try!(self.eval(item)) {
+            List(values) => Ok(values.move_iter().map(|v| vec!(v)).collect()),
+            Map(map)     => Ok(map.iter().map(|(k, v)| vec!(Str(k.clone()), v.clone())).collect()),
+            Nil          => Ok(Vec::new()),
+            v            => {
+                Err(error::eval_error(item.get_span(), format!("can't iterate over {}", v)))
+            }
+        }
+    }
+
+    // render the nested items once for each element, return false for an empty collection
+    fn render_loop(&mut self, item: &Item, indent: ~str) -> Result<bool, HamlError> {
+        let entries = try!(self.loop_entries(item));
+        let length = entries.len();
+        let vars = item.get_vars();
+        for (i, values) in entries.move_iter().enumerate() {
+            let mut locals = HashMap::new();
+            if vars.len() == values.len() {
+                for (var, value) in vars.iter().zip(values.move_iter()) {
+                    locals.insert(var.clone(), value);
+                }
+            } else if vars.len() == 1 {
+                // a key and a value in a single variable
+                locals.insert(vars.get(0).clone(), List(values));
+            } else {
+                return Err(error::eval_error(item.get_span(),
+                                             format!("can't bind {} variables to {}",
+                                                     vars.len(), List(values))))
+            }
+            let mut meta = TreeMap::new();
+            meta.insert("index".to_owned(), Int(i as i64));
+            meta.insert("first".to_owned(), Bool(i == 0));
+            meta.insert("last".to_owned(), Bool(i + 1 == length));
+            meta.insert("length".to_owned(), Int(length as i64));
+            locals.insert("loop".to_owned(), Map(meta));
+            self.scope.locals.push(locals);
+            let res = self.render_items(item, indent.clone());
+            self.scope.locals.pop();
+            try!(res);
+        }
+        Ok(length > 0)
+    }
+
+    fn load_partial(&mut self, item: &Item, name: &~str) -> Result<Rc<DomTree>, HamlError> {
+        match self.partials.find(name) {
+            Some(dom_tree) => return Ok(dom_tree.clone()),
+            None           => {}
+        }
+        let source = match self.loader {
+            Some(loader) => loader.load(name.as_slice()),
+            None         => Err("no template loader".to_owned())
+        };
+        let dom_tree = match source {
+            Ok(source) => match parse_template(source, self.opts) {
+                Ok(dom_tree) => Rc::new(dom_tree),
+                Err(e)       => return Err(error::in_partial(item.get_span(), name.clone(), e))
+            },
+            Err(msg)   => {
+                return Err(error::partial_not_found(item.get_span(), name.clone(), msg))
+            }
+        };
+        self.partials.insert(name.clone(), dom_tree.clone());
+        Ok(dom_tree)
+    }
+
+    // a partial only sees the render context and its locals
+    fn render_partial(&mut self, item: &Item, indent: ~str) -> Result<(), HamlError> {
+        let name = item.get_tag().to_owned();
+        if self.includes.contains(&name) {
+            let mut chain = self.includes.clone();
+            chain.push(name);
+            return Err(error::recursive_partial(item.get_span(), chain))
+        }
+        let dom_tree = try!(self.load_partial(item, &name));
+        let mut locals = HashMap::new();
+        for &(ref key, ref e) in item.get_locals().iter() {
+            match expr::eval(e, &self.scope, &self.opts.helpers) {
+                Ok(value) => { locals.insert(key.clone(), value); },
+                Err(msg)  => return Err(error::eval_error(item.get_span(), msg))
+            }
+        }
+        let outer = mem::replace(&mut self.scope.locals, vec!(locals));
+        self.includes.push(name.clone());
+        let res = self.render_items(dom_tree.get_root(), indent);
+        self.includes.pop();
+        self.scope.locals = outer;
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let recursive = match e.kind {
+                    error::RecursivePartial(_) => true,
+                    _                          => false
+                };
+                // a recursion is reported on the line of the first partial of the chain
+                if recursive {
+                    Err(HamlError::new(e.kind, item.get_span()))
+                } else {
+                    Err(error::in_partial(item.get_span(), name, e))
+                }
+            }
+        }
+    }
+
+    fn render_items(&mut self, elt: &Item, indent: ~str) -> Result<(), HamlError> {
+        // a branch of the current if (or the current loop) has already been rendered
+        let mut branch_taken = false;
+        for e in elt.get_childs().iter() {
+            let f_at = try!(self.format_attributes(e));
+            let tag = e.get_tag();
+            let (remove_outer_ws, remove_inner_ws) = e.get_whitespace_removal();
+            let escape = match e.get_escaping() {
+                Escaped         => true,
+                Unescaped       => false,
+                DefaultEscaping => self.opts.escape_html
+            };
+            let content = try!(self.eval_content(e, escape));
+            let preserve = self.opts.is_preserved(tag);
+            let content = if preserve {
+                filter::preserve(content)
+            } else if e.get_preserve() {
+                filter::find_and_preserve(content, self.opts.preserve.as_slice())
+            } else { content };
+            if remove_outer_ws { self.out.remove_whitespace(); }
+            match e.get_tag_type() {
+                PlainText   => self.out.push(format!("{}{}\n", indent, content)),
+                Inline      => self.out.push(format!("{}<{}{}>{}</{}>\n", indent,
+                                                     tag, f_at, content, tag)),
+                SelfClosing => {
+                    let close = if self.opts.html_fmt == Xhtml { " />" } else { ">" };
+                    self.out.push(format!("{}<{}{}{}\n", indent, tag, f_at, close))
+                },
+                Block if preserve && e.get_childs().len() != 0 => {
+                    // the childs are rendered without indentation on a single line
+                    let out = mem::replace(&mut self.out, Output::new());
+                    try!(self.render_items(e, "".to_owned()));
+                    let childs = mem::replace(&mut self.out, out);
+                    self.out.push(format!("{}<{}{}>{}</{}>\n", indent, tag, f_at,
+                                          filter::preserve(childs.buf.as_slice().trim_right()),
+                                          tag));
+                },
+                Block       => {
+                    if e.get_childs().len() == 0 {
+                        self.out.push(format!("{}<{}{}>", indent, tag, f_at));
+                    } else {
+                        self.out.push(format!("{}<{}{}>\n", indent, tag, f_at));
+                    }
+                    if remove_inner_ws { self.out.remove_whitespace(); }
+                    try!(self.render_items(e, indent + "  "));
+                    if remove_inner_ws { self.out.remove_whitespace(); }
+                    if e.get_childs().len() == 0 { self.out.push(format!("</{}>\n", tag)); }
+                    else { self.out.push(format!("{}</{}>\n", indent, tag)); }
+                },
+                HtmlComment => {
+                    if e.get_childs().len() == 0 { self.out.push(format!("{}<!-- ", indent)); }
+                    else { self.out.push(format!("{}<!--\n", indent)); }
+                    if !e.get_content().is_empty() { self.out.push(content.as_slice()); }
+                    else { try!(self.render_items(e, indent + "  ")); }
+                    if e.get_childs().len() == 0 { self.out.push(" -->\n"); }
+                    else { self.out.push(format!("{}-->\n", indent)); }
+                },
+                Header      => {
+                    self.out.push(format!("{}{}\n", indent,
+                                          format::get_specific_format(content.clone(),
+                                                                      self.opts.html_fmt)))
+                },
+                ConditionalComment | RevealedComment => {
+                    let (open, close) = if e.get_tag_type() == RevealedComment {
+                        (format!("<!--[{}]><!-->", tag), "<!--<![endif]-->".to_owned())
+                    } else {
+                        (format!("<!--[{}]>", tag), "<![endif]-->".to_owned())
+                    };
+                    if e.get_childs().len() == 0 {
+                        self.out.push(format!("{}{} {} {}\n", indent, open, content, close));
+                    } else {
+                        self.out.push(format!("{}{}\n", indent, open));
+                        try!(self.render_items(e, indent + "  "));
+                        self.out.push(format!("{}{}\n", indent, close));
+                    }
+                },
+                Filter      => self.out.push(filter::render(tag, content.as_slice(),
+                                                            self.opts.html_fmt,
+                                                            indent.as_slice())),
+                If | Unless | Elsif | Else => {
+                    // an if or an unless starts a new chain of branches
+                    let taken = match e.get_tag_type() {
+                        If | Unless => false,
+                        _           => branch_taken
+                    };
+                    let render = !taken && match e.get_tag_type() {
+                        Else   => true,
+                        Unless => !try!(self.eval(e)).is_true(),
+                        _      => try!(self.eval(e)).is_true()
+                    };
+                    branch_taken = taken || render;
+                    if render { try!(self.render_items(e, indent.clone())); }
+                },
+                For         => branch_taken = try!(self.render_loop(e, indent.clone())),
+                Partial     => try!(self.render_partial(e, indent.clone())),
+                _           => {}
+            }
+            if remove_outer_ws { self.out.remove_whitespace(); }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use collections::TreeMap;
+    use attributes::Attributes;
+    use context::Context;
+    use dom_tree::{DomTree, Item, Else};
+    use error::{HamlError, PartialNotFound, RecursivePartial};
+    use expr;
+    use format::Html5;
+    use interpolation;
+    use loader::{MemoryLoader, TemplateLoader};
+    use options::Options;
+    use span::Span;
+    use value::{Int, Map};
+
+    fn loop_tree(vars: Vec<~str>, collection: &str, text: &str) -> DomTree {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::for_loop(vars, expr::parse(collection).unwrap()));
+        dom_tree.insert(Item::plain_text(interpolation::compile(&interpolation::parse(text))
+                                         .unwrap()));
+        dom_tree.back();
+        dom_tree.back();
+        dom_tree.insert(Item::branch(Else, None));
+        dom_tree.insert(Item::block("p".to_owned(), Attributes::new()));
+        dom_tree
+    }
+
+    #[test]
+    fn loop_renders_the_nested_items_for_each_element() {
+        let mut ctx = Context::new();
+        ctx.set("items", vec!("a", "b", "c"));
+        let dom_tree = loop_tree(vec!("item".to_owned()), "items",
+                                 "#{loop.index}#{item}#{loop.first}#{loop.last}");
+        assert_eq!(Ok("0atruefalse\n1bfalsefalse\n2cfalsetrue\n".to_owned()),
+                   dom_tree.render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn loop_over_the_keys_and_values_of_a_map() {
+        let mut ctx = Context::new();
+        let mut map = TreeMap::new();
+        map.insert("a".to_owned(), Int(1));
+        map.insert("b".to_owned(), Int(2));
+        ctx.set("map", Map(map));
+        let dom_tree = loop_tree(vec!("k".to_owned(), "v".to_owned()), "map", "#{k}=#{v}");
+        assert_eq!(Ok("a=1\nb=2\n".to_owned()), dom_tree.render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn else_is_rendered_for_an_empty_collection() {
+        let mut ctx = Context::new();
+        ctx.set("items", Vec::<int>::new());
+        let dom_tree = loop_tree(vec!("item".to_owned()), "items", "#{item}");
+        assert_eq!(Ok("<p></p>\n".to_owned()), dom_tree.render(&Options::new(Html5), &ctx))
+        assert_eq!(Ok("<p></p>\n".to_owned()),
+                   dom_tree.render(&Options::new(Html5), &Context::new()))
+    }
+
+    #[test]
+    fn loop_variable_hides_the_context() {
+        let mut ctx = Context::new();
+        ctx.set("item", "outer");
+        ctx.set("items", vec!("inner"));
+        let mut dom_tree = loop_tree(vec!("item".to_owned()), "items", "#{item}");
+        dom_tree.back();
+        dom_tree.back();
+        dom_tree.insert(Item::plain_text(interpolation::compile(&interpolation::parse("#{item}"))
+                                         .unwrap()));
+        assert_eq!(Ok("inner\nouter\n".to_owned()), dom_tree.render(&Options::new(Html5), &ctx))
+    }
+
+    #[test]
+    fn loop_over_a_number_is_an_error() {
+        let mut ctx = Context::new();
+        ctx.set("items", 1i);
+        let dom_tree = loop_tree(vec!("item".to_owned()), "items", "#{item}");
+        assert!(dom_tree.render(&Options::new(Html5), &ctx).is_err())
+    }
+
+    fn partial_tree(name: &str) -> DomTree {
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::partial(name.to_owned(), Vec::new()).with_span(Span::line(1)));
+        dom_tree
+    }
+
+    #[test]
+    fn partial_sees_only_its_locals() {
+        let mut loader = MemoryLoader::new();
+        loader.add("card", "%p #{title}#{item}\n");
+        let mut ctx = Context::new();
+        ctx.set("items", vec!("a"));
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::for_loop(vec!("item".to_owned()), expr::parse("items").unwrap()));
+        let (name, locals) = expr::parse_partial("\"card\", title: item").unwrap();
+        dom_tree.insert(Item::partial(name, locals));
+        assert_eq!(Ok("<p>a</p>\n".to_owned()),
+                   dom_tree.render_with_loader(&Options::new(Html5), &ctx, &loader))
+    }
+
+    struct CountingLoader {
+        loads: Cell<uint>
+    }
+
+    impl TemplateLoader for CountingLoader {
+        fn load(&self, _: &str) -> Result<~str, ~str> {
+            self.loads.set(self.loads.get() + 1);
+            Ok("%p= item\n".to_owned())
+        }
+    }
+
+    #[test]
+    fn partial_is_parsed_once_per_render() {
+        let loader = CountingLoader { loads: Cell::new(0) };
+        let mut ctx = Context::new();
+        ctx.set("items", vec!("a", "b", "c"));
+        let mut dom_tree = DomTree::new();
+        dom_tree.insert(Item::for_loop(vec!("item".to_owned()), expr::parse("items").unwrap()));
+        let (name, locals) = expr::parse_partial("\"card\", item: item").unwrap();
+        dom_tree.insert(Item::partial(name, locals));
+        assert_eq!(Ok("<p>a</p>\n<p>b</p>\n<p>c</p>\n".to_owned()),
+                   dom_tree.render_with_loader(&Options::new(Html5), &ctx, &loader))
+        assert_eq!(1, loader.loads.get())
+    }
+
+    #[test]
+    fn missing_partial_is_an_error() {
+        let loader = MemoryLoader::new();
+        let dom_tree = partial_tree("card");
+        match dom_tree.render_with_loader(&Options::new(Html5), &Context::new(), &loader) {
+            Err(HamlError { kind: PartialNotFound(name, _), .. }) => {
+                assert_eq!("card".to_owned(), name)
+            },
+            res                                               => fail!("{}", res)
+        }
+        assert!(dom_tree.render(&Options::new(Html5), &Context::new()).is_err())
+    }
+
+    #[test]
+    fn recursive_partial_reports_the_include_chain() {
+        let mut loader = MemoryLoader::new();
+        loader.add("a", "%p\n  = render \"b\"\n");
+        loader.add("b", "= render \"a\"\n");
+        let dom_tree = partial_tree("a");
+        assert_eq!(Err(HamlError::new(RecursivePartial(vec!("a".to_owned(), "b".to_owned(),
+                                                            "a".to_owned())),
+                                      Span::line(1))),
+                   dom_tree.render_with_loader(&Options::new(Html5), &Context::new(), &loader))
+    }
+}
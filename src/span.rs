@@ -0,0 +1,89 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// position of a char in the source, line and col start at 1, offset is in bytes
+#[deriving(Clone, Show, Eq)]
+pub struct Position {
+    pub line: u32,
+    pub col: u32,
+    pub offset: uint
+}
+
+// a range of the source, len is in bytes
+#[deriving(Clone, Show, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+    pub offset: uint,
+    pub len: uint
+}
+
+impl Position {
+    pub fn new() -> Position {
+        Position { line: 1, col: 1, offset: 0 }
+    }
+
+    // position of the char following c
+    pub fn advance(&self, c: char) -> Position {
+        if c == '\n' {
+            Position { line: self.line + 1, col: 1, offset: self.offset + 1 }
+        } else {
+            Position { col: self.col + 1, offset: self.offset + c.len_utf8_bytes(), ..*self }
+        }
+    }
+}
+
+impl Span {
+    pub fn new(line: u32, col: u32, offset: uint, len: uint) -> Span {
+        Span { line: line, col: col, offset: offset, len: len }
+    }
+
+    // span of the source between start and end
+    pub fn from_positions(start: Position, end: Position) -> Span {
+        let len = if end.offset > start.offset { end.offset - start.offset } else { 0 };
+        Span::new(start.line, start.col, start.offset, len)
+    }
+
+    // used when the source is unknown, e.g. tokens built by hand
+    pub fn line(line: u32) -> Span {
+        Span::new(line, 1, 0, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use span::{Position, Span};
+
+    #[test]
+    fn advance_position() {
+        let p = Position::new().advance('a').advance('é');
+        assert_eq!(Position { line: 1, col: 3, offset: 3 }, p);
+        assert_eq!(Position { line: 2, col: 1, offset: 4 }, p.advance('\n'));
+    }
+
+    #[test]
+    fn span_from_positions() {
+        let start = Position::new().advance('%');
+        let end = start.advance('p');
+        assert_eq!(Span::new(1, 2, 1, 1), Span::from_positions(start, end));
+    }
+}
@@ -22,6 +22,8 @@
 
 #![allow(non_camel_case_types)]
 
+use interpolation::TextPart;
+
 #[deriving(Clone, Show, Eq)]
 pub enum Token {
     LPARENT,
@@ -32,21 +34,30 @@ pub enum Token {
     RBRACKET,
     RARROW,
     ASSIGN,
+    ESCAPED_ASSIGN,
+    UNESCAPED_ASSIGN,
+    ESCAPED,
+    UNESCAPED,
+    PRESERVE_ASSIGN,
     ID(~str),
     CLASS(~str),
     TAG(~str),
     ATTRIBUTE(~str),
     STR(~str),
     PLAIN_TEXT(~str),
+    INTERPOLATED(Vec<TextPart>),
+    FILTER(~str, ~str),
     HTML_COMMENT,
+    CONDITIONAL_COMMENT(~str),
+    REVEALED_COMMENT(~str),
     HAML_COMMENT,
+    SILENT_SCRIPT,
     INDENT(char, u32),
     DOCTYPE,
-    IDENTIFIER,
-    VAR,
-    DOUBLE_DOT,
-    NUMBER,
+    VAR(~str),
     CLOSING_EMPTY,
+    REMOVE_WS_OUTER,
+    REMOVE_WS_INNER,
     EOL,
     EOF
 }
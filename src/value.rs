@@ -0,0 +1,229 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang (letang.jeremy@gmail.com)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::vec::Vec;
+use collections::{HashMap, TreeMap};
+
+// the data given to a template
+#[deriving(Clone, Eq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(~str),
+    // html which is never escaped
+    Safe(~str),
+    List(Vec<Value>),
+    Map(TreeMap<~str, Value>)
+}
+
+// implemented by the types which can be given to a template, a struct is
+// usually converted to a Map of its fields.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl Value {
+    // the text written in the html
+    pub fn to_output(&self) -> ~str {
+        match *self {
+            Nil              => "".to_owned(),
+            Bool(b)          => b.to_str(),
+            Int(i)           => i.to_str(),
+            Float(f)         => f.to_str(),
+            Str(ref s)       => s.clone(),
+            Safe(ref s)      => s.clone(),
+            List(ref values) => {
+                let values: Vec<~str> = values.iter().map(|v| v.to_output()).collect();
+                values.as_slice().connect(" ")
+            },
+            Map(ref map)     => {
+                let values: Vec<~str> = map.iter().map(|(k, v)| {
+                    format!("{}: {}", k, v.to_output())
+                }).collect();
+                values.as_slice().connect(", ")
+            }
+        }
+    }
+
+    pub fn is_safe(&self) -> bool {
+        match *self {
+            Safe(_) => true,
+            _       => false
+        }
+    }
+
+    // only nil and false are false
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Nil      => false,
+            Bool(b)  => b,
+            _        => true
+        }
+    }
+
+    // the field of a map or the element of a list
+    pub fn get(&self, key: &str) -> Option<Value> {
+        match *self {
+            Map(ref map)     => map.find(&key.to_owned()).map(|v| v.clone()),
+            List(ref values) => {
+                match from_str::<uint>(key) {
+                    Some(i) if i < values.len() => Some(values.get(i).clone()),
+                    _                           => None
+                }
+            },
+            _                => None
+        }
+    }
+}
+
+impl fmt::Show for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Str(ref s) | Safe(ref s) => write!(f.buf, "\"{}\"", s),
+            List(ref values)         => write!(f.buf, "{}", values),
+            _                        => f.buf.write_str(self.to_output())
+        }
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value { self.clone() }
+}
+
+impl ToValue for ~str {
+    fn to_value(&self) -> Value { Str(self.clone()) }
+}
+
+impl<'a> ToValue for &'a str {
+    fn to_value(&self) -> Value { Str(self.to_owned()) }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value { Bool(*self) }
+}
+
+impl ToValue for int {
+    fn to_value(&self) -> Value { Int(*self as i64) }
+}
+
+impl ToValue for uint {
+    fn to_value(&self) -> Value { Int(*self as i64) }
+}
+
+impl ToValue for i32 {
+    fn to_value(&self) -> Value { Int(*self as i64) }
+}
+
+impl ToValue for i64 {
+    fn to_value(&self) -> Value { Int(*self) }
+}
+
+impl ToValue for f32 {
+    fn to_value(&self) -> Value { Float(*self as f64) }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value { Float(*self) }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match *self {
+            Some(ref v) => v.to_value(),
+            None        => Nil
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value { List(self.iter().map(|v| v.to_value()).collect()) }
+}
+
+impl<T: ToValue> ToValue for TreeMap<~str, T> {
+    fn to_value(&self) -> Value {
+        let mut map = TreeMap::new();
+        for (k, v) in self.iter() { map.insert(k.clone(), v.to_value()); }
+        Map(map)
+    }
+}
+
+impl<T: ToValue> ToValue for HashMap<~str, T> {
+    fn to_value(&self) -> Value {
+        let mut map = TreeMap::new();
+        for (k, v) in self.iter() { map.insert(k.clone(), v.to_value()); }
+        Map(map)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use collections::TreeMap;
+    use value::{Value, ToValue, Nil, Bool, Int, Str, List, Map};
+
+    struct User {
+        name: ~str,
+        age: int
+    }
+
+    impl ToValue for User {
+        fn to_value(&self) -> Value {
+            let mut map = TreeMap::new();
+            map.insert("name".to_owned(), self.name.to_value());
+            map.insert("age".to_owned(), self.age.to_value());
+            Map(map)
+        }
+    }
+
+    #[test]
+    fn convert_to_value() {
+        assert_eq!(Str("a".to_owned()), "a".to_value())
+        assert_eq!(Int(1), 1i.to_value())
+        assert_eq!(List(vec!(Bool(true), Nil)), vec!(Some(true), None).to_value())
+    }
+
+    #[test]
+    fn struct_fields_can_be_looked_up() {
+        let user = User { name: "John".to_owned(), age: 42 }.to_value();
+        assert_eq!(Some(Str("John".to_owned())), user.get("name"))
+        assert_eq!(None, user.get("email"))
+        assert_eq!("age: 42, name: John".to_owned(), user.to_output())
+    }
+
+    #[test]
+    fn list_elements_can_be_looked_up() {
+        let list = vec!("a", "b").to_value();
+        assert_eq!(Some(Str("b".to_owned())), list.get("1"))
+        assert_eq!(None, list.get("2"))
+        assert_eq!("a b".to_owned(), list.to_output())
+    }
+
+    #[test]
+    fn only_nil_and_false_are_false() {
+        assert!(!Nil.is_true())
+        assert!(!Bool(false).is_true())
+        assert!(Int(0).is_true())
+        assert!(Str("".to_owned()).is_true())
+    }
+}